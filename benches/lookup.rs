@@ -0,0 +1,56 @@
+//! 比较不同大小的值对查找吞吐量的影响. 键和值分开存放时, 查找只会扫描键数组, 值的大小不应明显影响耗时.
+//! 运行方式: cargo bench --bench lookup
+
+use std::collections::BTreeMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use naive_btree::Btree;
+
+const N: u64 = 200_000;
+const ROUNDS: usize = 5;
+
+/// 用乘法散列打乱查找顺序, 避免顺序访问掩盖缓存未命中
+fn probe(i: u64) -> u64
+{
+    i.wrapping_mul(0x9e37_79b9_7f4a_7c15) % (2 * N)
+}
+
+fn throughput(elapsed: Duration) -> f64
+{
+    (ROUNDS as u64 * 2 * N) as f64 / elapsed.as_secs_f64() / 1e6
+}
+
+fn bench_value_size<const SIZE: usize>()
+{
+    // 只插入偶数键, 这样一半的查找命中, 一半不命中
+    let mut btree = Btree::new();
+    let mut std_map = BTreeMap::new();
+    for k in 0..N {
+        btree.insert(2 * k, [k as u8; SIZE]);
+        std_map.insert(2 * k, [k as u8; SIZE]);
+    }
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        (0..2 * N).for_each(|i| { black_box(btree.get(&probe(i))); });
+    }
+    let btree_time = start.elapsed();
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        (0..2 * N).for_each(|i| { black_box(std_map.get(&probe(i))); });
+    }
+    let std_time = start.elapsed();
+
+    println!("{:>6} byte values: Btree {:>7.2} Mops/s, BTreeMap {:>7.2} Mops/s",
+        SIZE, throughput(btree_time), throughput(std_time));
+}
+
+fn main()
+{
+    bench_value_size::<8>();
+    bench_value_size::<64>();
+    bench_value_size::<256>();
+    bench_value_size::<1024>();
+}
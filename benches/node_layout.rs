@@ -0,0 +1,75 @@
+//! 比较节点存储布局的开销: 插入、查找、遍历、删除的耗时, 以及整个过程中的堆分配次数.
+//! 运行方式: cargo bench --bench node_layout
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use naive_btree::Btree;
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const N: usize = 1_000_000;
+
+/// xorshift 生成的不重复乱序键, 保证每次运行的数据一样
+fn shuffled_keys() -> Vec<u64>
+{
+    let mut keys: Vec<u64> = (0..N as u64).collect();
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    for i in (1..keys.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        keys.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+    keys
+}
+
+fn measure<T>(name: &str, f: impl FnOnce() -> T) -> T
+{
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    println!("{:<12} {:>10.2?} {:>12} allocations", name, elapsed, ALLOCATIONS.load(Ordering::Relaxed) - allocations);
+    result
+}
+
+fn main()
+{
+    let keys = shuffled_keys();
+
+    let mut btree = measure("insert", || {
+        let mut btree = Btree::new();
+        keys.iter().for_each(|&k| { btree.insert(k, k); });
+        btree
+    });
+
+    measure("get", || keys.iter().for_each(|k| { black_box(btree.get(k)); }));
+    measure("iter", || btree.iter().for_each(|item| { black_box(item); }));
+    measure("remove", || keys.iter().for_each(|k| { black_box(btree.remove(k)); }));
+    measure("drop", || drop(btree));
+}
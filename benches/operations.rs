@@ -0,0 +1,114 @@
+//! 核心操作的耗时, 以标准库的 BTreeMap 为基准. 调整 RANK 或者查找方式之后用它确认效果.
+//! 运行方式: cargo bench --bench operations
+
+use std::collections::BTreeMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use naive_btree::Btree;
+
+const N: u64 = 100_000;
+const ROUNDS: usize = 5;
+/// 每次区间扫描覆盖的键的数量
+const RANGE_WIDTH: u64 = 100;
+
+/// 两种树共同的操作, 让同一份测量代码可以跑在两种树上
+trait Map: Default
+{
+    fn insert(&mut self, key: u64, value: u64);
+    fn get(&self, key: &u64) -> Option<&u64>;
+    fn remove(&mut self, key: &u64);
+    fn iter_sum(&self) -> u64;
+    fn range_sum(&self, start: u64, end: u64) -> u64;
+}
+
+impl Map for Btree<u64, u64>
+{
+    fn insert(&mut self, key: u64, value: u64) { self.insert(key, value); }
+    fn get(&self, key: &u64) -> Option<&u64> { self.get(key) }
+    fn remove(&mut self, key: &u64) { black_box(self.remove(key)); }
+    fn iter_sum(&self) -> u64 { self.iter().map(|(_, v)| *v).sum() }
+    fn range_sum(&self, start: u64, end: u64) -> u64 { self.range(start..end).map(|(_, v)| *v).sum() }
+}
+
+impl Map for BTreeMap<u64, u64>
+{
+    fn insert(&mut self, key: u64, value: u64) { self.insert(key, value); }
+    fn get(&self, key: &u64) -> Option<&u64> { self.get(key) }
+    fn remove(&mut self, key: &u64) { black_box(self.remove(key)); }
+    fn iter_sum(&self) -> u64 { self.values().sum() }
+    fn range_sum(&self, start: u64, end: u64) -> u64 { self.range(start..end).map(|(_, v)| *v).sum() }
+}
+
+/// 0..N 的乱序排列, 用 xorshift 洗牌, 每次运行的数据一样
+fn shuffled() -> Vec<u64>
+{
+    let mut keys: Vec<u64> = (0..N).collect();
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    for i in (1..keys.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        keys.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+    keys
+}
+
+/// 只含偶数键的树, 查找奇数键就是不命中
+fn filled<M: Map>(keys: &[u64]) -> M
+{
+    let mut map = M::default();
+    keys.iter().for_each(|&k| map.insert(2 * k, k));
+    map
+}
+
+/// 每轮先用 setup 准备数据 (不计时), 再对它执行 op, 取最快的一轮
+fn fastest<S>(mut setup: impl FnMut() -> S, mut op: impl FnMut(&mut S)) -> Duration
+{
+    (0..ROUNDS).map(|_| {
+        let mut state = setup();
+        let start = Instant::now();
+        op(&mut state);
+        let elapsed = start.elapsed();
+        drop(state);
+        elapsed
+    }).min().unwrap()
+}
+
+fn workloads<M: Map>(keys: &[u64]) -> Vec<(&'static str, Duration)>
+{
+    let sorted: Vec<u64> = (0..N).collect();
+    let reverse: Vec<u64> = (0..N).rev().collect();
+    let insert = |order: &[u64]| fastest(M::default, |map| order.iter().for_each(|&k| map.insert(2 * k, k)));
+    let remove = |order: &[u64]| fastest(|| filled::<M>(keys), |map| order.iter().for_each(|&k| map.remove(&(2 * k))));
+    let map = filled::<M>(keys);
+
+    vec![
+        ("insert sorted", insert(&sorted)),
+        ("insert reverse", insert(&reverse)),
+        ("insert random", insert(keys)),
+        ("get hit", fastest(|| (), |_| keys.iter().for_each(|k| { black_box(map.get(&(2 * k))); }))),
+        ("get miss", fastest(|| (), |_| keys.iter().for_each(|k| { black_box(map.get(&(2 * k + 1))); }))),
+        // 和 remove_work / reverse_remove_work 一样按键的顺序和逆序删除
+        ("remove sorted", remove(&sorted)),
+        ("remove reverse", remove(&reverse)),
+        ("remove random", remove(keys)),
+        ("iter", fastest(|| (), |_| { black_box(map.iter_sum()); })),
+        ("range scan", fastest(|| (), |_| {
+            keys.iter().take((N / RANGE_WIDTH) as usize).for_each(|&k| { black_box(map.range_sum(2 * k, 2 * (k + RANGE_WIDTH))); })
+        }))
+    ]
+}
+
+fn main()
+{
+    let keys = shuffled();
+    let btree = workloads::<Btree<u64, u64>>(&keys);
+    let std_map = workloads::<BTreeMap<u64, u64>>(&keys);
+
+    println!("{N} entries, fastest of {ROUNDS} rounds");
+    println!("{:<16} {:>12} {:>12} {:>8}", "operation", "Btree", "BTreeMap", "ratio");
+    for ((name, btree_time), (_, std_time)) in btree.into_iter().zip(std_map) {
+        println!("{:<16} {:>12.2?} {:>12.2?} {:>8.2}", name, btree_time, std_time, btree_time.as_secs_f64() / std_time.as_secs_f64());
+    }
+}
@@ -0,0 +1,37 @@
+//! 比较释放整棵树的耗时: Btree 的析构和 clear, 以及标准库的 BTreeMap. 析构是循环, 不会随树高递归, 也不分配内存.
+//! 运行方式: cargo bench --bench teardown, 成员数量可以用环境变量 TEARDOWN_N 指定
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use naive_btree::Btree;
+
+const DEFAULT_N: usize = 20_000_000;
+
+fn timed(f: impl FnOnce()) -> Duration
+{
+    let start = Instant::now();
+    f();
+    start.elapsed()
+}
+
+fn main()
+{
+    let n = std::env::var("TEARDOWN_N").ok().and_then(|n| n.parse().ok()).unwrap_or(DEFAULT_N);
+    println!("{n} entries");
+
+    let btree = Btree::from_sorted_iter((0..n as u64).map(|k| (k, k)));
+    println!("{:<16} {:>10.2?}", "Btree drop", timed(|| drop(btree)));
+
+    // 值是 String 时每个成员的析构都要释放内存
+    let btree = Btree::from_sorted_iter((0..n as u64 / 4).map(|k| (k, k.to_string())));
+    println!("{:<16} {:>10.2?}", "Btree<_, String>", timed(|| drop(btree)));
+
+    let mut btree = Btree::from_sorted_iter((0..n as u64).map(|k| (k, k)));
+    let snapshot = btree.snapshot();
+    println!("{:<16} {:>10.2?}", "clear (shared)", timed(|| btree.clear()));
+    println!("{:<16} {:>10.2?}", "snapshot drop", timed(|| drop(snapshot)));
+
+    let std_map: BTreeMap<u64, u64> = (0..n as u64).map(|k| (k, k)).collect();
+    println!("{:<16} {:>10.2?}", "BTreeMap drop", timed(|| drop(std_map)));
+}
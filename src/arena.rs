@@ -0,0 +1,459 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem::{replace, take};
+use core::ops::{Index, IndexMut};
+
+use crate::error::key_not_found;
+use crate::inline_vec::InlineVec;
+use crate::search::search_keys;
+use crate::RANK;
+
+/// 节点在 slab 中的下标
+type NodeId = u32;
+
+#[derive(Clone)]
+struct ArenaNode<K, V>
+{
+    keys: InlineVec<K, RANK>,
+    vals: InlineVec<V, RANK>,
+    /// 叶子节点为空
+    children: InlineVec<NodeId, { RANK + 1 }>,
+    parent: Option<(NodeId, usize)>
+}
+
+impl<K, V> ArenaNode<K,V>
+{
+    const fn new() -> Self
+    {
+        Self{ keys: InlineVec::new(), vals: InlineVec::new(), children: InlineVec::new(), parent: None }
+    }
+
+    fn is_leaf(&self) -> bool
+    {
+        self.children.is_empty()
+    }
+}
+
+impl<K, V> Default for ArenaNode<K,V>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 节点全部存放在树自己持有的 slab 里, 节点之间用 u32 下标互相引用的 B 树.
+/// 和 [`Btree`](crate::Btree) 的接口相同, 另外支持保留容量的 `clear` 和整块复制 slab 的 `Clone`.
+#[derive(Clone)]
+pub struct ArenaBtree<K:Ord, V>
+{
+    nodes: Vec<ArenaNode<K,V>>,
+    /// 被合并掉的节点留下的空位, 分配新节点时优先复用
+    free: Vec<NodeId>,
+    root: NodeId
+}
+
+impl<K:Ord, V> ArenaBtree<K,V>
+{
+    pub fn new() -> Self
+    {
+        Self{ nodes: vec![ArenaNode::new()], free: Vec::new(), root: 0 }
+    }
+
+    /// 预先为 nodes 个节点分配 slab 空间
+    pub fn with_capacity(nodes: usize) -> Self
+    {
+        let mut tree = Self{ nodes: Vec::with_capacity(nodes.max(1)), free: Vec::new(), root: 0 };
+        tree.nodes.push(ArenaNode::new());
+        tree
+    }
+
+    /// 删除所有成员. slab 的容量保留下来, 之后的插入不需要重新分配节点.
+    pub fn clear(&mut self)
+    {
+        self.nodes.clear();
+        self.free.clear();
+        self.nodes.push(ArenaNode::new());
+        self.root = 0;
+    }
+
+    fn alloc(&mut self, node: ArenaNode<K,V>) -> NodeId
+    {
+        match self.free.pop()
+        {
+            Some(id) => {
+                self.nodes[id as usize] = node;
+                id
+            }
+            None => {
+                self.nodes.push(node);
+                NodeId::try_from(self.nodes.len() - 1).expect("节点数超出 u32 的范围")
+            }
+        }
+    }
+
+    fn release(&mut self, id: NodeId)
+    {
+        self.nodes[id as usize] = ArenaNode::new();
+        self.free.push(id);
+    }
+
+    fn node(&self, id: NodeId) -> &ArenaNode<K,V>
+    {
+        &self.nodes[id as usize]
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> &mut ArenaNode<K,V>
+    {
+        &mut self.nodes[id as usize]
+    }
+
+    /// 把 children[from ..] 的 parent 字段改为指向 id 和各自的下标
+    fn fix_children_parent(&mut self, id: NodeId, from: usize)
+    {
+        for i in from .. self.node(id).children.len() {
+            let child = self.node(id).children[i];
+            self.node_mut(child).parent = Some((id, i));
+        }
+    }
+
+    /// 找到返回 Ok((节点, 下标)), 找不到返回 Err((叶子节点, 插入位置))
+    fn search(&self, key: &K) -> Result<(NodeId, usize), (NodeId, usize)>
+    {
+        let mut id = self.root;
+        loop {
+            let node = self.node(id);
+            let index = match search_keys(&node.keys, key)
+            {
+                Ok(idx) => return Ok((id, idx)),
+                Err(idx) => idx
+            };
+            if node.is_leaf() { return Err((id, index)) }
+            id = node.children[index];
+        }
+    }
+
+    /// 一直沿着第一个子节点往下走, 得到子树中最左边的叶子节点.
+    /// 只通过裸指针读取 keys、children 和 parent 字段, 所以 IterMut 已经借出的 &mut V 不受影响.
+    unsafe fn first_leaf(nodes: *const ArenaNode<K,V>, mut id: NodeId) -> NodeId
+    {
+        unsafe {
+            loop {
+                let children = &(*nodes.add(id as usize)).children;
+                if children.is_empty() { return id }
+                id = children[0];
+            }
+        }
+    }
+
+    /// 和 Btree 的 get_next 一样, 返回中序遍历时的下一个成员位置, 对字段的访问限制同 first_leaf.
+    unsafe fn get_next(nodes: *const ArenaNode<K,V>, mut id: NodeId, mut index: usize, is_child_index: bool) -> Option<(NodeId, usize)>
+    {
+        unsafe {
+            if !is_child_index {
+                let children = &(*nodes.add(id as usize)).children;
+                if !children.is_empty() { return Some((Self::first_leaf(nodes, children[index + 1]), 0)) }
+                index += 1;
+            }
+            loop {
+                let node = nodes.add(id as usize);
+                let keys = &(*node).keys;
+                if index < keys.len() { return Some((id, index)) }
+                (id, index) = (*node).parent?;
+            }
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V>
+    {
+        match self.search(key)
+        {
+            Ok((id, idx)) => Some(&self.node(id).vals[idx]),
+            Err(_) => None
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V>
+    {
+        match self.search(key)
+        {
+            Ok((id, idx)) => Some(&mut self.node_mut(id).vals[idx]),
+            Err(_) => None
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    {
+        match self.search(&key)
+        {
+            Ok((id, idx)) => Some(replace(&mut self.node_mut(id).vals[idx], value)),
+            Err((id, idx)) => {
+                self.insert_at(id, idx, key, value);
+                None
+            }
+        }
+    }
+
+    /// 把成员插入 id 节点的 index 位置, 节点满了就分裂并把中间成员插入父节点, 直到不再分裂.
+    fn insert_at(&mut self, mut id: NodeId, mut index: usize, mut key: K, mut value: V)
+    {
+        let mut right_child = None;
+        loop {
+            let node = self.node_mut(id);
+            node.keys.insert(index, key);
+            node.vals.insert(index, value);
+            if let Some(right_child) = right_child {
+                node.children.insert(index + 1, right_child);
+                self.fix_children_parent(id, index + 1);
+            }
+
+            let node = self.node_mut(id);
+            if node.keys.len() < RANK { return }
+
+            let mid = RANK.div_ceil(2) - 1;
+            let right_node = ArenaNode{
+                keys: node.keys.split_off(mid + 1),
+                vals: node.vals.split_off(mid + 1),
+                children: if node.is_leaf() { InlineVec::new() } else { node.children.split_off(mid + 1) },
+                parent: None
+            };
+            (key, value) = (node.keys.pop().unwrap(), node.vals.pop().unwrap());
+            let parent = node.parent;
+
+            let right_id = self.alloc(right_node);
+            self.fix_children_parent(right_id, 0);
+
+            match parent
+            {
+                None => {
+                    let mut new_root = ArenaNode::new();
+                    new_root.keys.push(key);
+                    new_root.vals.push(value);
+                    new_root.children.push(id);
+                    new_root.children.push(right_id);
+                    self.root = self.alloc(new_root);
+                    self.fix_children_parent(self.root, 0);
+                    return;
+                }
+                Some((parent, parent_idx)) => {
+                    (id, index, right_child) = (parent, parent_idx, Some(right_id));
+                }
+            }
+        }
+    }
+
+    /// 从兄弟节点移动成员到本节点, origin 是 true 表示右边节点减少成员, origin 是 false 表示左边节点减少成员.
+    fn get_from_sibling(&mut self, id: NodeId, origin: bool)
+    {
+        let (parent, parent_idx) = self.node(id).parent.expect("必须要有父节点");
+        if origin {
+            let right_id = self.node(parent).children[parent_idx + 1];
+            let right = self.node_mut(right_id);
+            let (key, value) = (right.keys.remove(0), right.vals.remove(0));
+            let child = (!right.is_leaf()).then(|| right.children.remove(0));
+
+            let parent_node = self.node_mut(parent);
+            let key = replace(&mut parent_node.keys[parent_idx], key);
+            let value = replace(&mut parent_node.vals[parent_idx], value);
+
+            let node = self.node_mut(id);
+            node.keys.push(key);
+            node.vals.push(value);
+            if let Some(child) = child {
+                node.children.push(child);
+                let last = node.children.len() - 1;
+                self.fix_children_parent(id, last);
+                self.fix_children_parent(right_id, 0);
+            }
+        }
+        else {
+            let left_id = self.node(parent).children[parent_idx - 1];
+            let left = self.node_mut(left_id);
+            let (key, value) = (left.keys.pop().unwrap(), left.vals.pop().unwrap());
+            let child = left.children.pop();
+
+            let parent_node = self.node_mut(parent);
+            let key = replace(&mut parent_node.keys[parent_idx - 1], key);
+            let value = replace(&mut parent_node.vals[parent_idx - 1], value);
+
+            let node = self.node_mut(id);
+            node.keys.insert(0, key);
+            node.vals.insert(0, value);
+            if let Some(child) = child {
+                node.children.insert(0, child);
+                self.fix_children_parent(id, 0);
+            }
+        }
+    }
+
+    /// 合并同级两个兄弟节点, 把 id 的下一个节点合并到 id, 并释放右节点的位置
+    fn merge(&mut self, id: NodeId)
+    {
+        let (parent, parent_idx) = self.node(id).parent.expect("必须要有父节点");
+        let parent_node = self.node_mut(parent);
+        let (key, value) = (parent_node.keys.remove(parent_idx), parent_node.vals.remove(parent_idx));
+        let right_id = parent_node.children.remove(parent_idx + 1);
+        self.fix_children_parent(parent, parent_idx + 1);
+
+        let mut right = take(self.node_mut(right_id));
+        let node = self.node_mut(id);
+        let from = node.children.len();
+        node.keys.push(key);
+        node.vals.push(value);
+        node.keys.append(&mut right.keys);
+        node.vals.append(&mut right.vals);
+        node.children.append(&mut right.children);
+        self.fix_children_parent(id, from);
+        self.release(right_id);
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<(K,V)>
+    {
+        let (id, index) = self.search(key).ok()?;
+
+        let (mut current, deleted) = if self.node(id).is_leaf() {
+            let node = self.node_mut(id);
+            (id, (node.keys.remove(index), node.vals.remove(index)))
+        }
+        else {
+            let (leaf, idx) = unsafe { Self::get_next(self.nodes.as_ptr(), id, index, false).unwrap() };
+            let leaf_node = self.node_mut(leaf);
+            let (k, v) = (leaf_node.keys.remove(idx), leaf_node.vals.remove(idx));
+            let node = self.node_mut(id);
+            (leaf, (replace(&mut node.keys[index], k), replace(&mut node.vals[index], v)))
+        };
+
+        loop {
+            if self.node(current).keys.len() + 1 >= RANK.div_ceil(2) { break }
+
+            let Some((parent, parent_idx)) = self.node(current).parent else { break };
+
+            let siblings = &self.node(parent).children;
+            if parent_idx + 1 < siblings.len() && self.node(siblings[parent_idx + 1]).keys.len() + 1 > RANK.div_ceil(2) {
+                self.get_from_sibling(current, true);
+                break;
+            }
+            else if parent_idx > 0 && self.node(siblings[parent_idx - 1]).keys.len() + 1 > RANK.div_ceil(2) {
+                self.get_from_sibling(current, false);
+                break;
+            }
+            else {
+                if parent_idx + 1 < siblings.len() { self.merge(current) }
+                else { self.merge(siblings[parent_idx - 1]) }
+                current = parent;
+            }
+        }
+
+        let root = self.node(self.root);
+        if root.keys.is_empty() && !root.is_leaf() {
+            let new_root = root.children[0];
+            self.release(self.root);
+            self.node_mut(new_root).parent = None;
+            self.root = new_root;
+        }
+        Some(deleted)
+    }
+
+    pub fn iter(&self) -> Iter<'_, K,V>
+    {
+        let leaf = unsafe { Self::first_leaf(self.nodes.as_ptr(), self.root) };
+        Iter{ tree: self, current: Some((leaf, 0)), is_first: true }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, K,V>
+    {
+        let nodes = self.nodes.as_mut_ptr();
+        let leaf = unsafe { Self::first_leaf(nodes, self.root) };
+        IterMut{ nodes, current: Some((leaf, 0)), is_first: true, _marker: PhantomData }
+    }
+}
+
+impl<K:Ord, V> Default for ArenaBtree<K,V>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Iter<'a, K:Ord, V>
+{
+    tree: &'a ArenaBtree<K,V>,
+    current: Option<(NodeId, usize)>,
+    is_first: bool
+}
+
+impl<'a, K:Ord, V> Iterator for Iter<'a, K,V>
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        let (id, idx) = self.current?;
+        self.current = if self.is_first {
+            self.is_first = false;
+            (idx < self.tree.node(id).keys.len()).then_some((id, idx))
+        }
+        else {
+            unsafe { ArenaBtree::get_next(self.tree.nodes.as_ptr(), id, idx, false) }
+        };
+
+        let (id, idx) = self.current?;
+        let node = self.tree.node(id);
+        Some((&node.keys[idx], &node.vals[idx]))
+    }
+}
+
+pub struct IterMut<'a, K:Ord, V>
+{
+    nodes: *mut ArenaNode<K,V>,
+    current: Option<(NodeId, usize)>,
+    is_first: bool,
+    _marker: PhantomData<&'a mut ArenaNode<K,V>>
+}
+
+impl<'a, K:Ord, V> Iterator for IterMut<'a, K,V>
+{
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        let (id, idx) = self.current?;
+        self.current = if self.is_first {
+            self.is_first = false;
+            let keys = unsafe { &(*self.nodes.add(id as usize)).keys };
+            (idx < keys.len()).then_some((id, idx))
+        }
+        else {
+            unsafe { ArenaBtree::get_next(self.nodes, id, idx, false) }
+        };
+
+        // 每个成员只会被返回一次, 所以返回的 &mut V 之间不会重叠. 值通过裸指针取出, 不会借用整个 vals 数组.
+        let (id, idx) = self.current?;
+        unsafe {
+            let node = self.nodes.add(id as usize);
+            Some((&(&(*node).keys)[idx], &mut *InlineVec::as_mut_ptr(&raw mut (*node).vals).add(idx)))
+        }
+    }
+}
+
+impl<K:Ord, V> Index<K> for ArenaBtree<K,V>
+{
+    type Output = V;
+
+    fn index(&self, index: K) -> &Self::Output {
+        match self.get(&index) {
+            None => key_not_found(),
+            Some(val) => val
+        }
+    }
+}
+
+impl<K:Ord, V> IndexMut<K> for ArenaBtree<K,V>
+{
+    fn index_mut(&mut self, index: K) -> &mut Self::Output {
+        match self.get_mut(&index) {
+            None => key_not_found(),
+            Some(val) => val
+        }
+    }
+}
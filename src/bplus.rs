@@ -0,0 +1,611 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{Debug, Write};
+use core::marker::PhantomData;
+use core::mem::{replace, take};
+use core::ops::{Bound, Index, IndexMut, RangeBounds};
+
+use crate::error::key_not_found;
+use crate::inline_vec::InlineVec;
+use crate::search::{bound_index, search_keys};
+use crate::{escape_dot_label, OpCounters, TreeStats, RANK};
+
+type NodeId = u32;
+
+/// 叶子节点保存成员, 内部节点的 keys 只是分隔键: children[i] 中的键都小于 keys[i], children[i + 1] 中的键都不小于 keys[i].
+struct BplusNode<K, V>
+{
+    keys: InlineVec<K, RANK>,
+    /// 只有叶子节点使用
+    vals: InlineVec<V, RANK>,
+    /// 叶子节点为空
+    children: InlineVec<NodeId, { RANK + 1 }>,
+    parent: Option<(NodeId, usize)>,
+    /// 叶子节点之间的双向链表
+    prev: Option<NodeId>,
+    next: Option<NodeId>
+}
+
+impl<K, V> BplusNode<K,V>
+{
+    const fn new() -> Self
+    {
+        Self{
+            keys: InlineVec::new(),
+            vals: InlineVec::new(),
+            children: InlineVec::new(),
+            parent: None,
+            prev: None,
+            next: None
+        }
+    }
+
+    fn is_leaf(&self) -> bool
+    {
+        self.children.is_empty()
+    }
+}
+
+impl<K, V> Default for BplusNode<K,V>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 所有值都存放在叶子节点、叶子节点之间双向链接的 B+ 树. 遍历和区间查询沿着叶子链表前进, 不需要回到父节点.
+/// 公开方法和 [`Btree`](crate::Btree) 相同, 可以通过类型别名互相替换. 内部节点保存键的副本, 所以要求 `K: Clone`.
+pub struct BplusTree<K:Ord + Clone, V>
+{
+    nodes: Vec<BplusNode<K,V>>,
+    free: Vec<NodeId>,
+    root: NodeId,
+    /// 最左边和最右边的叶子节点
+    head: NodeId,
+    tail: NodeId,
+    counters: OpCounters
+}
+
+impl<K:Ord + Clone, V> BplusTree<K,V>
+{
+    pub fn new() -> Self
+    {
+        Self{ nodes: vec![BplusNode::new()], free: Vec::new(), root: 0, head: 0, tail: 0, counters: OpCounters::default() }
+    }
+
+    fn alloc(&mut self, node: BplusNode<K,V>) -> NodeId
+    {
+        match self.free.pop()
+        {
+            Some(id) => {
+                self.nodes[id as usize] = node;
+                id
+            }
+            None => {
+                self.nodes.push(node);
+                NodeId::try_from(self.nodes.len() - 1).expect("节点数超出 u32 的范围")
+            }
+        }
+    }
+
+    fn release(&mut self, id: NodeId)
+    {
+        self.nodes[id as usize] = BplusNode::new();
+        self.free.push(id);
+    }
+
+    fn node(&self, id: NodeId) -> &BplusNode<K,V>
+    {
+        &self.nodes[id as usize]
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> &mut BplusNode<K,V>
+    {
+        &mut self.nodes[id as usize]
+    }
+
+    fn fix_children_parent(&mut self, id: NodeId, from: usize)
+    {
+        for i in from .. self.node(id).children.len() {
+            let child = self.node(id).children[i];
+            self.node_mut(child).parent = Some((id, i));
+        }
+    }
+
+    /// 从根节点下降到可能包含 key 的叶子节点
+    fn find_leaf(&self, key: &K) -> NodeId
+    {
+        let mut id = self.root;
+        while !self.node(id).is_leaf() {
+            let node = self.node(id);
+            let index = bound_index(&node.keys, key, false);
+            id = node.children[index];
+        }
+        id
+    }
+
+    /// 找到返回 Ok((叶子节点, 下标)), 找不到返回 Err((叶子节点, 插入位置))
+    fn search(&self, key: &K) -> Result<(NodeId, usize), (NodeId, usize)>
+    {
+        let leaf = self.find_leaf(key);
+        search_keys(&self.node(leaf).keys, key).map(|idx| (leaf, idx)).map_err(|idx| (leaf, idx))
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V>
+    {
+        let (leaf, idx) = self.search(key).ok()?;
+        Some(&self.node(leaf).vals[idx])
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V>
+    {
+        let (leaf, idx) = self.search(key).ok()?;
+        Some(&mut self.node_mut(leaf).vals[idx])
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    {
+        match self.search(&key)
+        {
+            Ok((leaf, idx)) => Some(replace(&mut self.node_mut(leaf).vals[idx], value)),
+            Err((leaf, idx)) => {
+                let node = self.node_mut(leaf);
+                node.keys.insert(idx, key);
+                node.vals.insert(idx, value);
+                if node.keys.len() == RANK { self.split_leaf(leaf) }
+                None
+            }
+        }
+    }
+
+    /// 叶子节点分裂时左边保留多数成员, 右节点的第一个键复制一份作为分隔键插入父节点
+    fn split_leaf(&mut self, leaf: NodeId)
+    {
+        self.counters.splits += 1;
+        let node = self.node_mut(leaf);
+        let right = BplusNode{
+            keys: node.keys.split_off(RANK.div_ceil(2)),
+            vals: node.vals.split_off(RANK.div_ceil(2)),
+            children: InlineVec::new(),
+            parent: None,
+            prev: Some(leaf),
+            next: node.next
+        };
+        let separator = right.keys[0].clone();
+        let right_id = self.alloc(right);
+
+        match self.node(right_id).next {
+            Some(next) => self.node_mut(next).prev = Some(right_id),
+            None => self.tail = right_id
+        }
+        self.node_mut(leaf).next = Some(right_id);
+
+        self.insert_separator(leaf, separator, right_id);
+    }
+
+    /// left 分裂出了新的右兄弟 right, 把分隔键插入父节点, 父节点满了就继续分裂
+    fn insert_separator(&mut self, mut left: NodeId, mut separator: K, mut right: NodeId)
+    {
+        loop {
+            let Some((parent, parent_idx)) = self.node(left).parent else {
+                let mut new_root = BplusNode::new();
+                new_root.keys.push(separator);
+                new_root.children.push(left);
+                new_root.children.push(right);
+                self.root = self.alloc(new_root);
+                self.fix_children_parent(self.root, 0);
+                return;
+            };
+
+            let node = self.node_mut(parent);
+            node.keys.insert(parent_idx, separator);
+            node.children.insert(parent_idx + 1, right);
+            self.fix_children_parent(parent, parent_idx + 1);
+            if self.node(parent).keys.len() < RANK { return }
+
+            // 内部节点的分裂和 B 树相同, 中间的分隔键上移
+            self.counters.splits += 1;
+            let mid = RANK.div_ceil(2) - 1;
+            let node = self.node_mut(parent);
+            let mut new_node = BplusNode::new();
+            new_node.keys = node.keys.split_off(mid + 1);
+            new_node.children = node.children.split_off(mid + 1);
+            separator = node.keys.pop().unwrap();
+            right = self.alloc(new_node);
+            self.fix_children_parent(right, 0);
+            left = parent;
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<(K,V)>
+    {
+        let (leaf, idx) = self.search(key).ok()?;
+        let node = self.node_mut(leaf);
+        let removed = (node.keys.remove(idx), node.vals.remove(idx));
+
+        let mut current = leaf;
+        loop {
+            if self.node(current).keys.len() + 1 >= RANK.div_ceil(2) { break }
+            let Some((parent, parent_idx)) = self.node(current).parent else { break };
+
+            let siblings = &self.node(parent).children;
+            let has_right = parent_idx + 1 < siblings.len();
+            if has_right && self.node(siblings[parent_idx + 1]).keys.len() + 1 > RANK.div_ceil(2) {
+                self.get_from_sibling(current, true);
+                self.counters.borrows += 1;
+                break;
+            }
+            else if parent_idx > 0 && self.node(siblings[parent_idx - 1]).keys.len() + 1 > RANK.div_ceil(2) {
+                self.get_from_sibling(current, false);
+                self.counters.borrows += 1;
+                break;
+            }
+            else {
+                if has_right { self.merge(current) }
+                else { self.merge(siblings[parent_idx - 1]) }
+                self.counters.merges += 1;
+                current = parent;
+            }
+        }
+
+        let root = self.node(self.root);
+        if root.keys.is_empty() && !root.is_leaf() {
+            let new_root = root.children[0];
+            self.release(self.root);
+            self.node_mut(new_root).parent = None;
+            self.root = new_root;
+        }
+        Some(removed)
+    }
+
+    /// 从兄弟节点移动一个成员到本节点, origin 是 true 表示右边节点减少成员, origin 是 false 表示左边节点减少成员.
+    fn get_from_sibling(&mut self, id: NodeId, origin: bool)
+    {
+        let (parent, parent_idx) = self.node(id).parent.expect("必须要有父节点");
+        let is_leaf = self.node(id).is_leaf();
+        if origin {
+            let right_id = self.node(parent).children[parent_idx + 1];
+            let right = self.node_mut(right_id);
+            let key = right.keys.remove(0);
+            if is_leaf {
+                // 叶子节点直接移动成员, 父节点的分隔键改为右节点新的第一个键
+                let value = right.vals.remove(0);
+                let separator = right.keys[0].clone();
+                self.node_mut(parent).keys[parent_idx] = separator;
+                let node = self.node_mut(id);
+                node.keys.push(key);
+                node.vals.push(value);
+            }
+            else {
+                let child = right.children.remove(0);
+                let separator = replace(&mut self.node_mut(parent).keys[parent_idx], key);
+                let node = self.node_mut(id);
+                node.keys.push(separator);
+                node.children.push(child);
+                let last = node.children.len() - 1;
+                self.fix_children_parent(id, last);
+                self.fix_children_parent(right_id, 0);
+            }
+        }
+        else {
+            let left_id = self.node(parent).children[parent_idx - 1];
+            let left = self.node_mut(left_id);
+            let key = left.keys.pop().unwrap();
+            if is_leaf {
+                let value = left.vals.pop().unwrap();
+                self.node_mut(parent).keys[parent_idx - 1] = key.clone();
+                let node = self.node_mut(id);
+                node.keys.insert(0, key);
+                node.vals.insert(0, value);
+            }
+            else {
+                let child = left.children.pop().unwrap();
+                let separator = replace(&mut self.node_mut(parent).keys[parent_idx - 1], key);
+                let node = self.node_mut(id);
+                node.keys.insert(0, separator);
+                node.children.insert(0, child);
+                self.fix_children_parent(id, 0);
+            }
+        }
+    }
+
+    /// 把 id 的右兄弟合并到 id. 叶子节点合并时丢弃分隔键并从链表中摘掉右节点, 内部节点合并时分隔键下移.
+    fn merge(&mut self, id: NodeId)
+    {
+        let (parent, parent_idx) = self.node(id).parent.expect("必须要有父节点");
+        let parent_node = self.node_mut(parent);
+        let separator = parent_node.keys.remove(parent_idx);
+        let right_id = parent_node.children.remove(parent_idx + 1);
+        self.fix_children_parent(parent, parent_idx + 1);
+
+        let mut right = take(self.node_mut(right_id));
+        let node = self.node_mut(id);
+        if node.is_leaf() {
+            node.next = right.next;
+            match right.next {
+                Some(next) => self.node_mut(next).prev = Some(id),
+                None => self.tail = id
+            }
+        }
+        else {
+            node.keys.push(separator);
+        }
+
+        let node = self.node_mut(id);
+        let from = node.children.len();
+        node.keys.append(&mut right.keys);
+        node.vals.append(&mut right.vals);
+        node.children.append(&mut right.children);
+        self.fix_children_parent(id, from);
+        self.release(right_id);
+    }
+
+    /// 第一个满足 bound 下界的成员位置
+    fn lower_position(&self, bound: Bound<&K>) -> Option<(NodeId, usize)>
+    {
+        let (leaf, idx) = match bound
+        {
+            Bound::Unbounded => (self.head, 0),
+            Bound::Included(key) => {
+                let leaf = self.find_leaf(key);
+                (leaf, bound_index(&self.node(leaf).keys, key, true))
+            }
+            Bound::Excluded(key) => {
+                let leaf = self.find_leaf(key);
+                (leaf, bound_index(&self.node(leaf).keys, key, false))
+            }
+        };
+        if idx < self.node(leaf).keys.len() { Some((leaf, idx)) }
+        else { self.node(leaf).next.map(|next| (next, 0)) }
+    }
+
+    /// 最后一个满足 bound 上界的成员位置
+    fn upper_position(&self, bound: Bound<&K>) -> Option<(NodeId, usize)>
+    {
+        let (leaf, count) = match bound
+        {
+            Bound::Unbounded => (self.tail, self.node(self.tail).keys.len()),
+            Bound::Included(key) => {
+                let leaf = self.find_leaf(key);
+                (leaf, bound_index(&self.node(leaf).keys, key, false))
+            }
+            Bound::Excluded(key) => {
+                let leaf = self.find_leaf(key);
+                (leaf, bound_index(&self.node(leaf).keys, key, true))
+            }
+        };
+        if count > 0 { Some((leaf, count - 1)) }
+        else {
+            let prev = self.node(leaf).prev?;
+            Some((prev, self.node(prev).keys.len() - 1))
+        }
+    }
+
+    /// 按键的顺序遍历落在 range 内的成员. 起点大于终点时返回空迭代器.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Range<'_, K,V>
+    {
+        let front = self.lower_position(range.start_bound());
+        let back = self.upper_position(range.end_bound());
+        let (front, back) = match (front, back)
+        {
+            (Some(f), Some(b)) if self.node(f.0).keys[f.1].cmp(&self.node(b.0).keys[b.1]).is_le() => (Some(f), Some(b)),
+            _ => (None, None)
+        };
+        Range{ tree: self, front, back }
+    }
+
+    pub fn iter(&self) -> Iter<'_, K,V>
+    {
+        self.range(..)
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, K,V>
+    {
+        let current = (!self.node(self.head).keys.is_empty()).then_some((self.head, 0));
+        IterMut{ nodes: self.nodes.as_mut_ptr(), current, _marker: PhantomData }
+    }
+
+    /// 遍历整棵树, 统计各层节点数、填充率和内存占用. entries 只统计叶子节点中的成员.
+    pub fn stats(&self) -> TreeStats
+    {
+        let mut stats = TreeStats{
+            node_capacity: RANK - 1,
+            splits: self.counters.splits,
+            merges: self.counters.merges,
+            borrows: self.counters.borrows,
+            ..TreeStats::default()
+        };
+
+        let mut level = vec![self.root];
+        while !level.is_empty() {
+            stats.nodes_per_level.push(level.len());
+            let mut next_level = Vec::new();
+            for id in level {
+                let node = self.node(id);
+                if node.is_leaf() {
+                    stats.leaf_nodes += 1;
+                    stats.entries += node.keys.len();
+                }
+                else {
+                    stats.internal_nodes += 1;
+                    next_level.extend_from_slice(&node.children);
+                }
+            }
+            level = next_level;
+        }
+
+        // 叶子节点和内部节点共用一种布局
+        stats.leaf_bytes = stats.leaf_nodes * size_of::<BplusNode<K,V>>();
+        stats.internal_bytes = stats.internal_nodes * size_of::<BplusNode<K,V>>();
+        stats.height = stats.nodes_per_level.len();
+        stats.average_occupancy = stats.entries as f64 / (stats.leaf_nodes * stats.node_capacity) as f64;
+        stats
+    }
+}
+
+impl<K:Ord + Clone + Debug, V: Debug> BplusTree<K,V>
+{
+    fn node_label(&self, id: NodeId) -> String
+    {
+        let node = self.node(id);
+        if node.is_leaf() {
+            node.keys.iter().zip(node.vals.iter()).map(|(k, v)| format!("{:?}: {:?}", k, v)).collect::<Vec<_>>().join(", ")
+        }
+        else {
+            node.keys.iter().map(|k| format!("{:?}", k)).collect::<Vec<_>>().join(", ")
+        }
+    }
+
+    /// 生成 Graphviz 描述. 内部节点只显示分隔键, 叶子之间的链表画成虚线.
+    pub fn to_dot(&self) -> String
+    {
+        let mut out = String::from("digraph BplusTree {\n    node [shape=record];\n");
+        let mut level = vec![self.root];
+        while !level.is_empty() {
+            let mut next_level = Vec::new();
+            for id in level {
+                let _ = writeln!(out, "    n{} [label=\"{}\"];", id, escape_dot_label(&self.node_label(id)));
+                for &child in self.node(id).children.iter() {
+                    let _ = writeln!(out, "    n{} -> n{};", id, child);
+                }
+                if let Some(next) = self.node(id).next {
+                    let _ = writeln!(out, "    n{} -> n{} [style=dashed, constraint=false];", id, next);
+                }
+                next_level.extend_from_slice(&self.node(id).children);
+            }
+            level = next_level;
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// 逐层打印树的结构, 内部节点只显示分隔键.
+    pub fn dump_ascii(&self) -> String
+    {
+        let mut out = String::new();
+        let mut level = vec![self.root];
+        let mut depth = 0;
+        while !level.is_empty() {
+            let _ = write!(out, "L{}:", depth);
+            let mut next_level = Vec::new();
+            for id in level {
+                let _ = write!(out, " [{}]", self.node_label(id));
+                next_level.extend_from_slice(&self.node(id).children);
+            }
+            out.push('\n');
+            level = next_level;
+            depth += 1;
+        }
+        out
+    }
+}
+
+impl<K:Ord + Clone, V> Default for BplusTree<K,V>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 沿着叶子链表前进的双端迭代器, front 和 back 都是还没有返回的成员位置
+pub struct Range<'a, K:Ord + Clone, V>
+{
+    tree: &'a BplusTree<K,V>,
+    front: Option<(NodeId, usize)>,
+    back: Option<(NodeId, usize)>
+}
+
+pub type Iter<'a, K, V> = Range<'a, K, V>;
+
+impl<'a, K:Ord + Clone, V> Range<'a, K,V>
+{
+    fn item(&self, (id, idx): (NodeId, usize)) -> (&'a K, &'a V)
+    {
+        let node = self.tree.node(id);
+        (&node.keys[idx], &node.vals[idx])
+    }
+}
+
+impl<'a, K:Ord + Clone, V> Iterator for Range<'a, K,V>
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        let (id, idx) = self.front?;
+        if self.front == self.back {
+            (self.front, self.back) = (None, None);
+        }
+        else {
+            let node = self.tree.node(id);
+            self.front = if idx + 1 < node.keys.len() { Some((id, idx + 1)) } else { node.next.map(|next| (next, 0)) };
+        }
+        Some(self.item((id, idx)))
+    }
+}
+
+impl<K:Ord + Clone, V> DoubleEndedIterator for Range<'_, K,V>
+{
+    fn next_back(&mut self) -> Option<Self::Item>
+    {
+        let (id, idx) = self.back?;
+        if self.front == self.back {
+            (self.front, self.back) = (None, None);
+        }
+        else if idx > 0 {
+            self.back = Some((id, idx - 1));
+        }
+        else {
+            self.back = self.tree.node(id).prev.map(|prev| (prev, self.tree.node(prev).keys.len() - 1));
+        }
+        Some(self.item((id, idx)))
+    }
+}
+
+pub struct IterMut<'a, K, V>
+{
+    nodes: *mut BplusNode<K,V>,
+    current: Option<(NodeId, usize)>,
+    _marker: PhantomData<&'a mut BplusNode<K,V>>
+}
+
+impl<'a, K, V> Iterator for IterMut<'a, K,V>
+{
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        let (id, idx) = self.current?;
+        // 只通过裸指针访问字段, 不会借用已经返回过 &mut V 的 vals 数组
+        unsafe {
+            let node = self.nodes.add(id as usize);
+            let keys = &(*node).keys;
+            self.current = if idx + 1 < keys.len() { Some((id, idx + 1)) } else { (*node).next.map(|next| (next, 0)) };
+            Some((&keys[idx], &mut *InlineVec::as_mut_ptr(&raw mut (*node).vals).add(idx)))
+        }
+    }
+}
+
+impl<K:Ord + Clone, V> Index<K> for BplusTree<K,V>
+{
+    type Output = V;
+
+    fn index(&self, index: K) -> &Self::Output {
+        match self.get(&index) {
+            None => key_not_found(),
+            Some(val) => val
+        }
+    }
+}
+
+impl<K:Ord + Clone, V> IndexMut<K> for BplusTree<K,V>
+{
+    fn index_mut(&mut self, index: K) -> &mut Self::Output {
+        match self.get_mut(&index) {
+            None => key_not_found(),
+            Some(val) => val
+        }
+    }
+}
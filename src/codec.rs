@@ -0,0 +1,126 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// 把键和值编码成字节的方式, 供写入磁盘的树使用. 为自己的类型实现它就可以存入 [`PagedBtree`](crate::PagedBtree).
+///
+/// 整数使用小端序定长编码, String 和 Vec 先写 u32 长度再写内容.
+pub trait Codec: Sized
+{
+    /// 把 self 追加到 out 的末尾
+    fn encode(&self, out: &mut Vec<u8>);
+
+    /// 从 input 开头解码一个值并让 input 跳过用掉的字节. 数据不完整或者不合法时返回 None.
+    fn decode(input: &mut &[u8]) -> Option<Self>;
+}
+
+/// 从 input 开头取出 n 个字节
+pub(crate) fn take_bytes<'a>(input: &mut &'a [u8], n: usize) -> Option<&'a [u8]>
+{
+    if input.len() < n { return None }
+    let (head, tail) = input.split_at(n);
+    *input = tail;
+    Some(head)
+}
+
+macro_rules! int_codec {
+    ($($ty:ty),*) => {$(
+        impl Codec for $ty
+        {
+            fn encode(&self, out: &mut Vec<u8>)
+            {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+
+            fn decode(input: &mut &[u8]) -> Option<Self>
+            {
+                let bytes = take_bytes(input, size_of::<$ty>())?;
+                Some(<$ty>::from_le_bytes(bytes.try_into().unwrap()))
+            }
+        }
+    )*};
+}
+
+int_codec!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl Codec for bool
+{
+    fn encode(&self, out: &mut Vec<u8>)
+    {
+        out.push(*self as u8);
+    }
+
+    fn decode(input: &mut &[u8]) -> Option<Self>
+    {
+        match take_bytes(input, 1)? {
+            [0] => Some(false),
+            [1] => Some(true),
+            _ => None
+        }
+    }
+}
+
+impl Codec for ()
+{
+    fn encode(&self, _: &mut Vec<u8>) {}
+
+    fn decode(_: &mut &[u8]) -> Option<Self>
+    {
+        Some(())
+    }
+}
+
+impl Codec for String
+{
+    fn encode(&self, out: &mut Vec<u8>)
+    {
+        (self.len() as u32).encode(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(input: &mut &[u8]) -> Option<Self>
+    {
+        let len = u32::decode(input)? as usize;
+        String::from_utf8(take_bytes(input, len)?.to_vec()).ok()
+    }
+}
+
+impl<T: Codec> Codec for Vec<T>
+{
+    fn encode(&self, out: &mut Vec<u8>)
+    {
+        (self.len() as u32).encode(out);
+        self.iter().for_each(|item| item.encode(out));
+    }
+
+    fn decode(input: &mut &[u8]) -> Option<Self>
+    {
+        let len = u32::decode(input)? as usize;
+        // 长度字段损坏时不要预先分配巨大的内存
+        let mut items = Vec::with_capacity(len.min(input.len()));
+        for _ in 0..len {
+            items.push(T::decode(input)?);
+        }
+        Some(items)
+    }
+}
+
+impl<A: Codec, B: Codec> Codec for (A, B)
+{
+    fn encode(&self, out: &mut Vec<u8>)
+    {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Option<Self>
+    {
+        Some((A::decode(input)?, B::decode(input)?))
+    }
+}
+
+/// FNV-1a 校验和, 用来发现写了一半或者损坏的数据
+#[cfg(feature = "std")]
+pub(crate) fn checksum(bytes: &[u8]) -> u32
+{
+    bytes.iter().fold(0x811c_9dc5, |hash, &byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193))
+}
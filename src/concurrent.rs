@@ -0,0 +1,303 @@
+use std::boxed::Box;
+use std::cmp::Ordering as CmpOrdering;
+use std::mem::replace;
+use std::ptr::NonNull;
+
+#[cfg(loom)]
+use loom::sync::{atomic::{AtomicUsize, Ordering}, RwLock, RwLockWriteGuard};
+#[cfg(not(loom))]
+use std::sync::{atomic::{AtomicUsize, Ordering}, RwLock, RwLockWriteGuard};
+
+use crate::inline_vec::InlineVec;
+use crate::search::search_keys;
+use crate::RANK;
+
+/// 节点最多保存的成员数, 插入之前不需要预留分裂用的空位
+const MAX_LEN: usize = RANK - 1;
+/// 自顶向下的分裂和合并要求 2 * MIN_LEN + 1 <= MAX_LEN, 所以非根节点最少只保留一个成员
+const MIN_LEN: usize = 1;
+
+struct ConcurrentNode<K, V>
+{
+    keys: InlineVec<K, RANK>,
+    vals: InlineVec<V, RANK>,
+    /// 叶子节点为空
+    children: InlineVec<NodePtr<K,V>, { RANK + 1 }>
+}
+
+/// 每个节点都有自己的读写锁, 节点通过 Box::leak 分配
+type NodePtr<K,V> = NonNull<RwLock<ConcurrentNode<K,V>>>;
+type WriteGuard<'a, K,V> = RwLockWriteGuard<'a, ConcurrentNode<K,V>>;
+
+impl<K, V> ConcurrentNode<K,V>
+{
+    fn new() -> Self
+    {
+        Self{ keys: InlineVec::new(), vals: InlineVec::new(), children: InlineVec::new() }
+    }
+
+    fn is_leaf(&self) -> bool
+    {
+        self.children.is_empty()
+    }
+
+    fn alloc(self) -> NodePtr<K,V>
+    {
+        NonNull::from(Box::leak(Box::new(RwLock::new(self))))
+    }
+}
+
+impl<K, V> Drop for ConcurrentNode<K,V>
+{
+    fn drop(&mut self) {
+        for &child in self.children.iter() {
+            unsafe { drop(Box::from_raw(child.as_ptr())) };
+        }
+    }
+}
+
+/// 取得节点的锁. 调用者保证在返回的引用使用期间节点不会被释放:
+/// 释放节点之前必须同时持有它的父节点和它自己的写锁, 而其他线程只能在持有父节点的锁时拿到节点的指针.
+unsafe fn latch<'a, K, V>(node: NodePtr<K,V>) -> &'a RwLock<ConcurrentNode<K,V>>
+{
+    unsafe { &*node.as_ptr() }
+}
+
+/// 支持多线程同时读写的 B 树, 所有操作都只需要 &self.
+///
+/// 每个节点有一把读写锁, 操作从根节点向下逐层加锁 (lock coupling): 拿到子节点的锁之后才释放父节点的锁.
+/// 插入在向下的途中预先分裂满的子节点, 删除预先让子节点多于最少成员数, 所以修改不会再向上传递,
+/// 任何时候一个线程最多持有相邻两层的锁, 加锁顺序总是自上而下, 不会死锁.
+pub struct ConcurrentBtree<K:Ord, V>
+{
+    /// 根节点指针也有一把锁, 替换根节点时需要它的写锁
+    root: RwLock<NodePtr<K,V>>,
+    len: AtomicUsize
+}
+
+unsafe impl<K:Ord + Send + Sync, V: Send + Sync> Send for ConcurrentBtree<K,V> {}
+unsafe impl<K:Ord + Send + Sync, V: Send + Sync> Sync for ConcurrentBtree<K,V> {}
+
+impl<K:Ord, V> Default for ConcurrentBtree<K,V>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K:Ord, V> Drop for ConcurrentBtree<K,V>
+{
+    fn drop(&mut self) {
+        let root = *self.root.get_mut().unwrap();
+        unsafe { drop(Box::from_raw(root.as_ptr())) };
+    }
+}
+
+impl<K:Ord, V> ConcurrentBtree<K,V>
+{
+    pub fn new() -> Self
+    {
+        Self{ root: RwLock::new(ConcurrentNode::new().alloc()), len: AtomicUsize::new(0) }
+    }
+
+    /// 其他线程同时修改时只是某一时刻的近似值
+    pub fn len(&self) -> usize
+    {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.len() == 0
+    }
+
+    /// 在读锁下对找到的值调用 f
+    pub fn get_with<R>(&self, key: &K, f: impl FnOnce(&V) -> R) -> Option<R>
+    {
+        let root = self.root.read().unwrap();
+        let mut guard = unsafe { latch(*root) }.read().unwrap();
+        drop(root);
+        loop {
+            match search_keys(&guard.keys, key)
+            {
+                Ok(idx) => return Some(f(&guard.vals[idx])),
+                Err(_) if guard.is_leaf() => return None,
+                Err(idx) => guard = unsafe { latch(guard.children[idx]) }.read().unwrap()
+            }
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V>
+    where V: Clone
+    {
+        self.get_with(key, V::clone)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool
+    {
+        self.get_with(key, |_| ()).is_some()
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V>
+    {
+        let mut root = self.root.write().unwrap();
+        let mut guard = unsafe { latch(*root) }.write().unwrap();
+        if guard.keys.len() == MAX_LEN {
+            let mut new_root = ConcurrentNode::new();
+            new_root.children.push(*root);
+            split_child(&mut new_root, 0, &mut guard);
+            *root = new_root.alloc();
+            guard = unsafe { latch(*root) }.write().unwrap();
+        }
+        // 根节点没有满, 不会再被替换
+        drop(root);
+
+        loop {
+            match search_keys(&guard.keys, &key)
+            {
+                Ok(idx) => return Some(replace(&mut guard.vals[idx], value)),
+                Err(idx) if guard.is_leaf() => {
+                    guard.keys.insert(idx, key);
+                    guard.vals.insert(idx, value);
+                    self.len.fetch_add(1, Ordering::Relaxed);
+                    return None;
+                }
+                Err(mut idx) => {
+                    let mut child = unsafe { latch(guard.children[idx]) }.write().unwrap();
+                    if child.keys.len() == MAX_LEN {
+                        split_child(&mut guard, idx, &mut child);
+                        match key.cmp(&guard.keys[idx])
+                        {
+                            CmpOrdering::Less => {}
+                            CmpOrdering::Equal => return Some(replace(&mut guard.vals[idx], value)),
+                            CmpOrdering::Greater => {
+                                idx += 1;
+                                child = unsafe { latch(guard.children[idx]) }.write().unwrap();
+                            }
+                        }
+                    }
+                    guard = child;
+                }
+            }
+        }
+    }
+
+    pub fn remove(&self, key: &K) -> Option<(K,V)>
+    {
+        // 根节点的子节点合并之后根节点可能变空, 所以在离开根节点之前一直持有根节点指针的锁
+        let mut root = Some(self.root.write().unwrap());
+        let mut guard = unsafe { latch(**root.as_ref().unwrap()) }.write().unwrap();
+
+        loop {
+            let found = search_keys(&guard.keys, key);
+            if guard.is_leaf() {
+                let idx = found.ok()?;
+                self.len.fetch_sub(1, Ordering::Relaxed);
+                return Some((guard.keys.remove(idx), guard.vals.remove(idx)));
+            }
+
+            // 被删除的成员在内部节点时用右子树的最小成员替换它, 所以同样进入右边的子节点
+            fix_child(&mut guard, found.map_or_else(|idx| idx, |idx| idx + 1));
+
+            if let Some(root) = root.as_mut().filter(|_| guard.keys.is_empty()) {
+                let only = guard.children.pop().unwrap();
+                drop(guard);
+                unsafe { drop(Box::from_raw(root.as_ptr())) };
+                **root = only;
+                guard = unsafe { latch(only) }.write().unwrap();
+                continue;
+            }
+
+            // 调整子节点可能把成员移动到别的节点, 重新查找一次
+            match search_keys(&guard.keys, key)
+            {
+                Ok(idx) => {
+                    let child = unsafe { latch(guard.children[idx + 1]) }.write().unwrap();
+                    drop(root);
+                    let (key, value) = remove_first(child);
+                    self.len.fetch_sub(1, Ordering::Relaxed);
+                    return Some((replace(&mut guard.keys[idx], key), replace(&mut guard.vals[idx], value)));
+                }
+                Err(idx) => {
+                    let child = unsafe { latch(guard.children[idx]) }.write().unwrap();
+                    root = None;
+                    guard = child;
+                }
+            }
+        }
+    }
+}
+
+/// 把满的子节点 child (即 parent.children[idx]) 分成两个节点, 中间的成员上移到 parent. parent 不能是满的.
+fn split_child<K, V>(parent: &mut ConcurrentNode<K,V>, idx: usize, child: &mut ConcurrentNode<K,V>)
+{
+    let mid = MAX_LEN / 2;
+    let right = ConcurrentNode{
+        keys: child.keys.split_off(mid + 1),
+        vals: child.vals.split_off(mid + 1),
+        children: if child.is_leaf() { InlineVec::new() } else { child.children.split_off(mid + 1) }
+    };
+    parent.keys.insert(idx, child.keys.pop().unwrap());
+    parent.vals.insert(idx, child.vals.pop().unwrap());
+    parent.children.insert(idx + 1, right.alloc());
+}
+
+/// 让 parent.children[idx] 的成员多于 MIN_LEN, 之后从它删除一个成员不会让它过少.
+/// 先向兄弟借成员, 兄弟也不够时和兄弟合并. parent 是根节点时可能因此变空.
+fn fix_child<K, V>(parent: &mut ConcurrentNode<K,V>, idx: usize)
+{
+    let mut child = unsafe { latch(parent.children[idx]) }.write().unwrap();
+    if child.keys.len() > MIN_LEN { return }
+
+    if idx + 1 < parent.children.len() {
+        let mut right = unsafe { latch(parent.children[idx + 1]) }.write().unwrap();
+        if right.keys.len() > MIN_LEN {
+            let key = replace(&mut parent.keys[idx], right.keys.remove(0));
+            let value = replace(&mut parent.vals[idx], right.vals.remove(0));
+            child.keys.push(key);
+            child.vals.push(value);
+            if !right.is_leaf() { child.children.push(right.children.remove(0)); }
+        }
+        else {
+            merge(parent, idx, &mut child, right);
+        }
+    }
+    else {
+        let mut left = unsafe { latch(parent.children[idx - 1]) }.write().unwrap();
+        if left.keys.len() > MIN_LEN {
+            let key = replace(&mut parent.keys[idx - 1], left.keys.pop().unwrap());
+            let value = replace(&mut parent.vals[idx - 1], left.vals.pop().unwrap());
+            child.keys.insert(0, key);
+            child.vals.insert(0, value);
+            if let Some(grandchild) = left.children.pop() { child.children.insert(0, grandchild); }
+        }
+        else {
+            merge(parent, idx - 1, &mut left, child);
+        }
+    }
+}
+
+/// 把 parent.children[idx + 1] 和它们之间的成员合并到 left (即 parent.children[idx]), 然后释放右节点.
+/// 持有 parent 和右节点的写锁时, 其他线程不可能再拿到右节点的指针.
+fn merge<K, V>(parent: &mut ConcurrentNode<K,V>, idx: usize, left: &mut ConcurrentNode<K,V>, mut right: WriteGuard<'_, K,V>)
+{
+    left.keys.push(parent.keys.remove(idx));
+    left.vals.push(parent.vals.remove(idx));
+    left.keys.append(&mut right.keys);
+    left.vals.append(&mut right.vals);
+    left.children.append(&mut right.children);
+
+    let right_ptr = parent.children.remove(idx + 1);
+    drop(right);
+    unsafe { drop(Box::from_raw(right_ptr.as_ptr())) };
+}
+
+/// 删除以 guard 为根的子树中最小的成员, 向下的途中同样预先调整子节点
+fn remove_first<K, V>(mut guard: WriteGuard<'_, K,V>) -> (K,V)
+{
+    loop {
+        if guard.is_leaf() { return (guard.keys.remove(0), guard.vals.remove(0)) }
+        fix_child(&mut guard, 0);
+        guard = unsafe { latch(guard.children[0]) }.write().unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::vec::Vec;
+
+use crate::codec::{checksum, Codec};
+use crate::error::check_increasing;
+use crate::{Allocator, Btree, RANK};
+
+const MAGIC: &[u8; 8] = b"NBTDUMP\0";
+const VERSION: u32 = 1;
+/// magic, 版本, 阶数, 成员数, 数据长度, 校验和
+const HEADER_LEN: usize = 8 + 4 + 4 + 8 + 8 + 4;
+
+/// [`Btree::read_from`] 拒绝输入的原因
+#[derive(Debug)]
+pub enum LoadError
+{
+    Io(io::Error),
+    /// 不是 write_to 写出的文件
+    BadMagic,
+    UnsupportedVersion(u32),
+    /// 文件由不同阶数的树写出
+    OrderMismatch{ expected: u32, found: u32 },
+    /// 文件在头部或者数据中间结束
+    Truncated,
+    ChecksumMismatch,
+    /// 校验和正确但是内容无法解码, 或者键不是严格递增的
+    Corrupted
+}
+
+impl fmt::Display for LoadError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self
+        {
+            LoadError::Io(err) => write!(f, "读取失败: {err}"),
+            LoadError::BadMagic => write!(f, "不是 Btree 的转储文件"),
+            LoadError::UnsupportedVersion(version) => write!(f, "不支持的版本 {version}"),
+            LoadError::OrderMismatch{ expected, found } => write!(f, "阶数不匹配: 需要 {expected}, 文件是 {found}"),
+            LoadError::Truncated => write!(f, "文件不完整"),
+            LoadError::ChecksumMismatch => write!(f, "校验和错误"),
+            LoadError::Corrupted => write!(f, "文件内容损坏")
+        }
+    }
+}
+
+impl Error for LoadError
+{
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self
+        {
+            LoadError::Io(err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for LoadError
+{
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::UnexpectedEof { LoadError::Truncated } else { LoadError::Io(err) }
+    }
+}
+
+impl<K:Ord + Codec, V: Codec, A: Allocator> Btree<K,V,A>
+{
+    /// 以带版本的二进制格式写出所有成员: 文件头 (magic, 版本, 阶数, 成员数, 数据长度, 校验和) 之后是按键排序的成员.
+    /// 成员先在内存中编码以便计算校验和.
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()>
+    {
+        let mut body = Vec::new();
+        let mut count = 0u64;
+        for (key, value) in self.iter() {
+            key.encode(&mut body);
+            value.encode(&mut body);
+            count += 1;
+        }
+
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(MAGIC);
+        VERSION.encode(&mut header);
+        (RANK as u32).encode(&mut header);
+        count.encode(&mut header);
+        (body.len() as u64).encode(&mut header);
+        checksum(&body).encode(&mut header);
+
+        writer.write_all(&header)?;
+        writer.write_all(&body)?;
+        writer.flush()
+    }
+}
+
+impl<K:Ord + Codec, V: Codec> Btree<K,V>
+{
+    /// 读取 write_to 写出的数据. 成员已经排好序, 所以直接自底向上构建节点, 不需要逐个插入.
+    pub fn read_from(mut reader: impl Read) -> Result<Self, LoadError>
+    {
+        let mut header = [0; HEADER_LEN];
+        reader.read_exact(&mut header)?;
+        let input = &mut &header[8..];
+        if &header[..8] != MAGIC { return Err(LoadError::BadMagic) }
+        let version = u32::decode(input).unwrap();
+        if version != VERSION { return Err(LoadError::UnsupportedVersion(version)) }
+        let order = u32::decode(input).unwrap();
+        if order != RANK as u32 { return Err(LoadError::OrderMismatch{ expected: RANK as u32, found: order }) }
+        let count = u64::decode(input).unwrap();
+        let body_len = u64::decode(input).unwrap();
+        let expected_checksum = u32::decode(input).unwrap();
+
+        // 按实际读到的数据增长缓冲区, 损坏的长度字段不会导致巨大的分配
+        let mut body = Vec::new();
+        reader.take(body_len).read_to_end(&mut body)?;
+        if body.len() as u64 != body_len { return Err(LoadError::Truncated) }
+        if checksum(&body) != expected_checksum { return Err(LoadError::ChecksumMismatch) }
+
+        let input = &mut &body[..];
+        let mut entries: Vec<(K,V)> = Vec::new();
+        for _ in 0..count {
+            let (Some(key), Some(value)) = (K::decode(input), V::decode(input)) else { return Err(LoadError::Corrupted) };
+            if entries.last().is_some_and(|(last, _)| check_increasing(last, &key).is_err()) { return Err(LoadError::Corrupted) }
+            entries.push((key, value));
+        }
+        if !input.is_empty() { return Err(LoadError::Corrupted) }
+        Ok(Self::build_sorted(entries))
+    }
+}
@@ -0,0 +1,87 @@
+use core::fmt::{self, Debug, Display};
+
+/// 分配节点的内存失败. 返回它的操作不会修改树.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocError;
+
+impl Display for AllocError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("内存分配失败")
+    }
+}
+
+impl core::error::Error for AllocError {}
+
+/// 树的各种不 panic 的操作返回的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error
+{
+    /// 树中没有要查找的键
+    KeyNotFound,
+    /// 要求键各不相同的输入中有重复的键
+    DuplicateKey,
+    /// 要求键递增的输入中键的顺序不对
+    UnsortedKeys,
+    /// 数据超出了结构能表示的大小
+    CapacityExceeded,
+    Alloc(AllocError)
+}
+
+impl Display for Error
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self
+        {
+            Error::KeyNotFound => f.write_str("集合内没有这个键"),
+            Error::DuplicateKey => f.write_str("键重复"),
+            Error::UnsortedKeys => f.write_str("键不是递增的"),
+            Error::CapacityExceeded => f.write_str("超出容量"),
+            Error::Alloc(err) => Display::fmt(err, f)
+        }
+    }
+}
+
+impl core::error::Error for Error
+{
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self
+        {
+            Error::Alloc(err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<AllocError> for Error
+{
+    fn from(err: AllocError) -> Self {
+        Error::Alloc(err)
+    }
+}
+
+/// Index 找不到键时的 panic. Index 不要求键实现 Debug, 所以消息中没有键
+#[track_caller]
+pub(crate) fn key_not_found() -> !
+{
+    panic!("{}", Error::KeyNotFound)
+}
+
+/// [`Btree::expect`](crate::Btree::expect) 找不到键时的 panic, 消息中带上键的 Debug 输出
+#[track_caller]
+pub(crate) fn key_not_found_debug<K: Debug>(key: &K) -> !
+{
+    panic!("{}: {key:?}", Error::KeyNotFound)
+}
+
+/// 检查相邻的两个键是否严格递增
+pub(crate) fn check_increasing<K: Ord>(prev: &K, next: &K) -> Result<(), Error>
+{
+    match prev.cmp(next)
+    {
+        core::cmp::Ordering::Less => Ok(()),
+        core::cmp::Ordering::Equal => Err(Error::DuplicateKey),
+        core::cmp::Ordering::Greater => Err(Error::UnsortedKeys)
+    }
+}
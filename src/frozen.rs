@@ -0,0 +1,257 @@
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+use std::vec;
+use std::vec::Vec;
+
+use crate::codec::Codec;
+use crate::{Allocator, Btree, Error, LoadError};
+
+const MAGIC: &[u8; 8] = b"NBTFROZ1";
+const VERSION: u32 = 1;
+/// magic, 版本, 成员数, 数据长度
+const HEADER_LEN: usize = 8 + 4 + 4 + 8;
+
+/// 只读的冻结树. 所有内容在一块连续, 与地址无关的字节中:
+/// 文件头之后是按键排序的成员偏移表 (len + 1 个 u32), 然后是 Eytzinger 顺序的搜索索引 (len 个 u32, 存成员序号),
+/// 最后是用 [`Codec`] 编码的成员. 查找沿着 Eytzinger 索引做二分, 遍历和范围查询按偏移表顺序读取.
+///
+/// 字节可以写入文件以后原样读回, 打开时只检查文件头和各部分的长度, 不做反序列化,
+/// 键和值在访问时才解码. B 可以是 `Vec<u8>`, `&[u8]` 或者打开 `mmap` feature 后由 [`FrozenBtree::open`] 返回的内存映射.
+/// 内容损坏的数据不会导致未定义行为, 但是访问到损坏的部分时会 panic.
+pub struct FrozenBtree<K, V, B = Vec<u8>>
+{
+    bytes: B,
+    len: usize,
+    _marker: PhantomData<fn() -> (K, V)>
+}
+
+impl<K:Ord + Codec, V: Codec> FrozenBtree<K,V>
+{
+    /// 编码后的成员超过 4GiB 时 panic
+    pub fn from_btree<A: Allocator>(tree: &Btree<K,V,A>) -> Self
+    {
+        Self::try_from_btree(tree).unwrap_or_else(|_| panic!("冻结树的数据不能超过 4GiB"))
+    }
+
+    /// 和 from_btree 一样, 编码后的成员超过 4GiB 时返回 Error::CapacityExceeded
+    pub fn try_from_btree<A: Allocator>(tree: &Btree<K,V,A>) -> Result<Self, Error>
+    {
+        let mut data = Vec::new();
+        let mut offsets = vec![0u32];
+        for (key, value) in tree.iter() {
+            key.encode(&mut data);
+            value.encode(&mut data);
+            offsets.push(u32::try_from(data.len()).map_err(|_| Error::CapacityExceeded)?);
+        }
+        let len = offsets.len() - 1;
+        let mut eytzinger = vec![0u32; len];
+        fill_eytzinger(&mut eytzinger, &mut 0, 1);
+
+        let mut bytes = Vec::with_capacity(HEADER_LEN + (offsets.len() + eytzinger.len()) * 4 + data.len());
+        bytes.extend_from_slice(MAGIC);
+        VERSION.encode(&mut bytes);
+        (len as u32).encode(&mut bytes);
+        (data.len() as u64).encode(&mut bytes);
+        offsets.iter().chain(&eytzinger).for_each(|n| n.encode(&mut bytes));
+        bytes.extend_from_slice(&data);
+        Ok(FrozenBtree{ bytes, len, _marker: PhantomData })
+    }
+}
+
+/// 按中序给以 i 为根 (从 1 开始编号) 的隐式完全二叉树填入递增的成员序号
+fn fill_eytzinger(eytzinger: &mut [u32], next: &mut u32, i: usize)
+{
+    if i > eytzinger.len() { return }
+    fill_eytzinger(eytzinger, next, 2 * i);
+    eytzinger[i - 1] = *next;
+    *next += 1;
+    fill_eytzinger(eytzinger, next, 2 * i + 1);
+}
+
+#[cfg(feature = "mmap")]
+impl<K:Ord + Codec, V: Codec> FrozenBtree<K,V,memmap2::Mmap>
+{
+    /// 把 write_to 写出的文件映射到内存中使用. 映射期间文件不能被修改或者截断.
+    pub fn open(path: impl AsRef<std::path::Path>) -> Result<Self, LoadError>
+    {
+        let file = std::fs::File::open(path)?;
+        // 文件只会被读取, 调用者保证映射期间没有其他人修改它
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Self::from_bytes(map)
+    }
+}
+
+impl<K:Ord + Codec, V: Codec, B: AsRef<[u8]>> FrozenBtree<K,V,B>
+{
+    /// 使用 as_bytes 或者 write_to 得到的字节, 只检查文件头和长度
+    pub fn from_bytes(bytes: B) -> Result<Self, LoadError>
+    {
+        let slice = bytes.as_ref();
+        if slice.len() < HEADER_LEN { return Err(LoadError::Truncated) }
+        if &slice[..8] != MAGIC { return Err(LoadError::BadMagic) }
+        let input = &mut &slice[8..HEADER_LEN];
+        let version = u32::decode(input).unwrap();
+        if version != VERSION { return Err(LoadError::UnsupportedVersion(version)) }
+        let len = u32::decode(input).unwrap() as usize;
+        let data_len = u64::decode(input).unwrap();
+        // 文件头可能是伪造的, 长度的计算不能溢出
+        let expected = (2 * len as u64 + 1).checked_mul(4)
+            .and_then(|index_len| index_len.checked_add(HEADER_LEN as u64))
+            .and_then(|header_len| header_len.checked_add(data_len))
+            .ok_or(LoadError::Corrupted)?;
+        if (slice.len() as u64) < expected { return Err(LoadError::Truncated) }
+        if slice.len() as u64 > expected { return Err(LoadError::Corrupted) }
+        Ok(FrozenBtree{ bytes, len, _marker: PhantomData })
+    }
+
+    pub fn as_bytes(&self) -> &[u8]
+    {
+        self.bytes.as_ref()
+    }
+
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()>
+    {
+        writer.write_all(self.as_bytes())?;
+        writer.flush()
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.len == 0
+    }
+
+    fn word(&self, idx: usize) -> usize
+    {
+        let pos = HEADER_LEN + idx * 4;
+        u32::from_le_bytes(self.as_bytes()[pos..pos + 4].try_into().unwrap()) as usize
+    }
+
+    /// 第 idx 个成员编码后的字节
+    fn entry(&self, idx: usize) -> &[u8]
+    {
+        let data = &self.as_bytes()[HEADER_LEN + (2 * self.len + 1) * 4..];
+        data.get(self.word(idx)..self.word(idx + 1)).expect("冻结树的数据已损坏")
+    }
+
+    fn decode_entry(&self, idx: usize) -> (K, V)
+    {
+        let input = &mut self.entry(idx);
+        K::decode(input).zip(V::decode(input)).expect("冻结树的数据已损坏")
+    }
+
+    fn key_at(&self, idx: usize) -> K
+    {
+        K::decode(&mut self.entry(idx)).expect("冻结树的数据已损坏")
+    }
+
+    /// Eytzinger 索引中第 i 个 (从 1 开始) 位置的成员序号
+    fn eytzinger(&self, i: usize) -> usize
+    {
+        let idx = self.word(self.len + i);
+        assert!(idx < self.len, "冻结树的数据已损坏");
+        idx
+    }
+
+    pub fn get(&self, key: &K) -> Option<V>
+    {
+        let mut i = 1;
+        while i <= self.len {
+            let input = &mut self.entry(self.eytzinger(i));
+            let found = K::decode(input).expect("冻结树的数据已损坏");
+            match found.cmp(key)
+            {
+                std::cmp::Ordering::Less => i = 2 * i + 1,
+                std::cmp::Ordering::Greater => i *= 2,
+                std::cmp::Ordering::Equal => return Some(V::decode(input).expect("冻结树的数据已损坏"))
+            }
+        }
+        None
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool
+    {
+        self.get(key).is_some()
+    }
+
+    /// 第一个大于等于 key (after 为 true 时是大于 key) 的成员序号, 没有时返回 len
+    fn lower_bound(&self, key: &K, after: bool) -> usize
+    {
+        let (mut i, mut found) = (1, self.len);
+        while i <= self.len {
+            let idx = self.eytzinger(i);
+            let current = self.key_at(idx);
+            let order = current.cmp(key);
+            if order.is_lt() || (after && order.is_eq()) {
+                i = 2 * i + 1;
+            }
+            else {
+                found = idx;
+                i *= 2;
+            }
+        }
+        found
+    }
+
+    pub fn iter(&self) -> Iter<'_, K,V,B>
+    {
+        Iter{ tree: self, front: 0, back: self.len }
+    }
+
+    /// 按键的顺序遍历落在 range 内的成员. 起点大于终点时返回空迭代器.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Iter<'_, K,V,B>
+    {
+        let front = match range.start_bound()
+        {
+            Bound::Unbounded => 0,
+            Bound::Included(key) => self.lower_bound(key, false),
+            Bound::Excluded(key) => self.lower_bound(key, true)
+        };
+        let back = match range.end_bound()
+        {
+            Bound::Unbounded => self.len,
+            Bound::Included(key) => self.lower_bound(key, true),
+            Bound::Excluded(key) => self.lower_bound(key, false)
+        };
+        Iter{ tree: self, front, back: back.max(front) }
+    }
+}
+
+/// FrozenBtree 的迭代器, 每次解码出一个键值对
+pub struct Iter<'a, K, V, B>
+{
+    tree: &'a FrozenBtree<K,V,B>,
+    front: usize,
+    back: usize
+}
+
+impl<K:Ord + Codec, V: Codec, B: AsRef<[u8]>> Iterator for Iter<'_, K,V,B>
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        if self.front == self.back { return None }
+        self.front += 1;
+        Some(self.tree.decode_entry(self.front - 1))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.back - self.front, Some(self.back - self.front))
+    }
+}
+
+impl<K:Ord + Codec, V: Codec, B: AsRef<[u8]>> DoubleEndedIterator for Iter<'_, K,V,B>
+{
+    fn next_back(&mut self) -> Option<(K, V)> {
+        if self.front == self.back { return None }
+        self.back -= 1;
+        Some(self.tree.decode_entry(self.back))
+    }
+}
+
+impl<K:Ord + Codec, V: Codec, B: AsRef<[u8]>> ExactSizeIterator for Iter<'_, K,V,B> {}
@@ -0,0 +1,127 @@
+use core::mem::MaybeUninit;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::slice;
+
+/// 容量固定为 N、元素直接存放在结构体内的 Vec, 超出容量时 panic.
+/// 供使用下标或者 Arc 连接节点的树变体使用, 让节点本身只占一块内存.
+pub(crate) struct InlineVec<T, const N: usize>
+{
+    len: usize,
+    data: [MaybeUninit<T>; N]
+}
+
+impl<T, const N: usize> InlineVec<T, N>
+{
+    pub(crate) const fn new() -> Self
+    {
+        Self{ len: 0, data: [const { MaybeUninit::uninit() }; N] }
+    }
+
+    /// 只通过裸指针得到元素数组的起始地址, 不会产生覆盖整个数组的引用
+    pub(crate) unsafe fn as_mut_ptr(this: *mut Self) -> *mut T
+    {
+        unsafe { (&raw mut (*this).data).cast() }
+    }
+
+    pub(crate) fn push(&mut self, value: T)
+    {
+        assert!(self.len < N, "InlineVec 容量不足");
+        self.data[self.len].write(value);
+        self.len += 1;
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<T>
+    {
+        if self.len == 0 { return None }
+        self.len -= 1;
+        Some(unsafe { self.data[self.len].assume_init_read() })
+    }
+
+    pub(crate) fn insert(&mut self, index: usize, value: T)
+    {
+        assert!(self.len < N, "InlineVec 容量不足");
+        assert!(index <= self.len, "插入位置越界");
+        unsafe {
+            let p = self.data.as_mut_ptr().cast::<T>();
+            ptr::copy(p.add(index), p.add(index + 1), self.len - index);
+            p.add(index).write(value);
+        }
+        self.len += 1;
+    }
+
+    pub(crate) fn remove(&mut self, index: usize) -> T
+    {
+        assert!(index < self.len, "删除位置越界");
+        unsafe {
+            let p = self.data.as_mut_ptr().cast::<T>();
+            let value = p.add(index).read();
+            ptr::copy(p.add(index + 1), p.add(index), self.len - index - 1);
+            self.len -= 1;
+            value
+        }
+    }
+
+    /// 把 [at, len) 的元素移动到新的 InlineVec 中返回
+    pub(crate) fn split_off(&mut self, at: usize) -> Self
+    {
+        assert!(at <= self.len, "分割位置越界");
+        let mut other = Self::new();
+        unsafe {
+            ptr::copy_nonoverlapping(self.data.as_ptr().add(at), other.data.as_mut_ptr(), self.len - at);
+        }
+        other.len = self.len - at;
+        self.len = at;
+        other
+    }
+
+    /// 把 other 的全部元素移动到本数组的末尾, other 变为空
+    pub(crate) fn append(&mut self, other: &mut Self)
+    {
+        assert!(self.len + other.len <= N, "InlineVec 容量不足");
+        unsafe {
+            ptr::copy_nonoverlapping(other.data.as_ptr(), self.data.as_mut_ptr().add(self.len), other.len);
+        }
+        self.len += other.len;
+        other.len = 0;
+    }
+}
+
+impl<T, const N: usize> Deref for InlineVec<T, N>
+{
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.data.as_ptr().cast(), self.len) }
+    }
+}
+
+impl<T, const N: usize> DerefMut for InlineVec<T, N>
+{
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.data.as_mut_ptr().cast(), self.len) }
+    }
+}
+
+impl<T: Clone, const N: usize> Clone for InlineVec<T, N>
+{
+    fn clone(&self) -> Self {
+        let mut other = Self::new();
+        self.iter().for_each(|item| other.push(item.clone()));
+        other
+    }
+}
+
+impl<T, const N: usize> Default for InlineVec<T, N>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for InlineVec<T, N>
+{
+    fn drop(&mut self) {
+        unsafe { ptr::drop_in_place(self.deref_mut()) }
+    }
+}
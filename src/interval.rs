@@ -0,0 +1,242 @@
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::ops::{Bound, RangeBounds, RangeInclusive};
+
+use crate::{Btree, Node};
+
+/// IntervalMap 在 Btree 中存放的值
+#[derive(Clone)]
+struct Entry<K, V>
+{
+    end: K,
+    /// 本区间的终点和左边的子树中终点的最大值, 节点的最后一个成员还包括最右边的子树.
+    /// 所以一个节点所有成员的 max_end 的最大值就是整棵子树的最大终点
+    max_end: K,
+    value: V
+}
+
+type IntervalNode<K,V> = Node<K, Entry<K,V>>;
+
+/// Btree 的 augment 函数: 由成员的终点和子节点的 max_end 重新计算节点中每个成员的 max_end
+unsafe fn update_max_end<K:Ord + Clone, V>(node: *mut IntervalNode<K,V>)
+{
+    unsafe {
+        let (len, children) = ((*node).len, Node::children(node));
+        for idx in 0..len {
+            let covered = if children.is_empty() { &[][..] } else if idx + 1 == len { &children[idx..] } else { &children[idx..=idx] };
+            let max_end = covered.iter()
+                .filter_map(|&child| subtree_max_end(child))
+                .fold(&Node::vals(node)[idx].end, |max, end| if end.cmp(max).is_gt() { end } else { max })
+                .clone();
+            (*Node::val_ptr(node).add(idx)).max_end = max_end;
+        }
+    }
+}
+
+/// 子树中终点的最大值, 空的根节点返回 None
+unsafe fn subtree_max_end<'a, K:Ord + 'a, V: 'a>(node: *const IntervalNode<K,V>) -> Option<&'a K>
+{
+    unsafe { Node::vals(node).iter().map(|entry| &entry.max_end).max_by(|a, b| a.cmp(b)) }
+}
+
+/// 区间树. 闭区间 [start, end] 以起点为键存放在 Btree 中, 每个起点只对应一个区间.
+/// 树在每次修改之后更新节点中记录的子树最大终点, 查询重叠的区间时跳过最大终点太小的子树.
+/// 更新发生在树的结构调整完之后, 这时 K 的 clone 或者比较 panic 不会破坏树, 插入或者删除仍然生效,
+/// 但是之后的查询可能漏掉区间, len 也可能不准确.
+pub struct IntervalMap<K:Ord + Clone, V>
+{
+    tree: Btree<K, Entry<K,V>>,
+    len: usize
+}
+
+impl<K:Ord + Clone, V> Default for IntervalMap<K,V>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K:Ord + Clone, V: Clone> Clone for IntervalMap<K,V>
+{
+    fn clone(&self) -> Self {
+        let mut map = Self::new();
+        for (start, end, value) in self.iter() {
+            map.insert(start.clone()..=end.clone(), value.clone());
+        }
+        map
+    }
+}
+
+impl<K:Ord + Clone, V> IntervalMap<K,V>
+{
+    pub fn new() -> Self
+    {
+        Self{ tree: Btree::new_augmented(update_max_end), len: 0 }
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.len == 0
+    }
+
+    /// 插入区间 start..=end. 已经有相同起点的区间时替换它的终点和值, 返回原来的终点和值. 起点大于终点时 panic.
+    pub fn insert(&mut self, interval: RangeInclusive<K>, value: V) -> Option<(K, V)>
+    {
+        let (start, end) = interval.into_inner();
+        assert!(start.cmp(&end).is_le(), "区间的起点不能大于终点");
+        let old = self.tree.insert(start, Entry{ max_end: end.clone(), end, value });
+        if old.is_none() { self.len += 1; }
+        old.map(|entry| (entry.end, entry.value))
+    }
+
+    /// 起点为 start 的区间的终点和值
+    pub fn get(&self, start: &K) -> Option<(&K, &V)>
+    {
+        self.tree.get(start).map(|entry| (&entry.end, &entry.value))
+    }
+
+    /// 删除起点为 start 的区间, 返回区间和值
+    pub fn remove(&mut self, start: &K) -> Option<(RangeInclusive<K>, V)>
+    {
+        let (start, entry) = self.tree.remove(start)?;
+        self.len -= 1;
+        Some((start..=entry.end, entry.value))
+    }
+
+    /// 按起点顺序遍历所有区间
+    pub fn iter(&self) -> Overlapping<'_, K,V>
+    {
+        self.overlapping(..)
+    }
+
+    /// 按起点顺序遍历和 range 有交集的区间. 终点的最大值不在 range 内的子树不会被访问.
+    pub fn overlapping<R: RangeBounds<K>>(&self, range: R) -> Overlapping<'_, K,V>
+    {
+        let (lower, upper) = (range.start_bound().cloned(), range.end_bound().cloned());
+        let empty = match (&lower, &upper)
+        {
+            (Bound::Included(a), Bound::Included(b)) => a.cmp(b).is_gt(),
+            (Bound::Included(a) | Bound::Excluded(a), Bound::Included(b) | Bound::Excluded(b)) => a.cmp(b).is_ge(),
+            _ => false
+        };
+        let mut iter = Overlapping{ stack: Vec::new(), lower, upper, _marker: PhantomData };
+        if !empty && iter.may_overlap(self.tree.root) { iter.stack.push((self.tree.root, 0)); }
+        iter
+    }
+
+    /// 按起点顺序遍历包含 point 的区间
+    pub fn containing(&self, point: &K) -> Overlapping<'_, K,V>
+    {
+        self.overlapping(point.clone()..=point.clone())
+    }
+}
+
+/// 和查询区间有交集的区间的迭代器, 按起点顺序返回 (起点, 终点, 值).
+/// 栈里保存节点和进行到的位置: 偶数 2i 表示接下来访问第 i 个子节点, 奇数 2i + 1 表示接下来检查第 i 个成员.
+pub struct Overlapping<'a, K:Ord, V>
+{
+    stack: Vec<(*const IntervalNode<K,V>, usize)>,
+    lower: Bound<K>,
+    upper: Bound<K>,
+    _marker: PhantomData<&'a (K, V)>
+}
+
+impl<K:Ord, V> Overlapping<'_, K,V>
+{
+    /// 终点满足查询的下界时区间才可能和查询区间有交集
+    fn end_reaches(&self, end: &K) -> bool
+    {
+        match &self.lower
+        {
+            Bound::Unbounded => true,
+            Bound::Included(lower) => end.cmp(lower).is_ge(),
+            Bound::Excluded(lower) => end.cmp(lower).is_gt()
+        }
+    }
+
+    /// 起点超过查询的上界时, 这个区间和之后的区间都不可能有交集
+    fn start_passed(&self, start: &K) -> bool
+    {
+        match &self.upper
+        {
+            Bound::Unbounded => false,
+            Bound::Included(upper) => start.cmp(upper).is_gt(),
+            Bound::Excluded(upper) => start.cmp(upper).is_ge()
+        }
+    }
+
+    fn may_overlap(&self, node: *const IntervalNode<K,V>) -> bool
+    {
+        unsafe { subtree_max_end(node) }.is_some_and(|max_end| self.end_reaches(max_end))
+    }
+}
+
+impl<'a, K:Ord, V> Iterator for Overlapping<'a, K,V>
+{
+    type Item = (&'a K, &'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        loop {
+            let (node, pos) = *self.stack.last()?;
+            self.stack.last_mut().unwrap().1 += 1;
+            let idx = pos / 2;
+            // 迭代器借用着 IntervalMap, 期间节点不会被修改或者释放
+            let (keys, entries, children) = unsafe { (Node::keys(node), Node::vals(node), Node::children(node)) };
+            if pos % 2 == 0 {
+                if let Some(&child) = children.get(idx).filter(|&&child| self.may_overlap(child)) {
+                    self.stack.push((child, 0));
+                }
+            }
+            else if idx >= keys.len() {
+                self.stack.pop();
+            }
+            else if self.start_passed(&keys[idx]) {
+                self.stack.clear();
+                return None
+            }
+            else if self.end_reaches(&entries[idx].end) {
+                return Some((&keys[idx], &entries[idx].end, &entries[idx].value))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+use super::*;
+use alloc::collections::BTreeMap;
+
+/// 有快照时删除会先复制被共享的兄弟节点再向它借成员, 要更新的是复制出来的节点
+#[test]
+fn remove_with_live_snapshot()
+{
+    let (mut map, mut expected) = (IntervalMap::new(), BTreeMap::new());
+    let mut state = 1u32;
+    let mut random = || { state ^= state << 13; state ^= state >> 17; state ^= state << 5; state };
+    for start in 0..400 {
+        let end = start + random() % 60;
+        map.insert(start..=end, ());
+        expected.insert(start, end);
+    }
+
+    while !expected.is_empty() {
+        let snapshot = map.tree.snapshot();
+        let start = *expected.keys().nth(random() as usize % expected.len()).unwrap();
+        assert!(map.remove(&start).is_some());
+        expected.remove(&start);
+        for point in (0..460).step_by(5) {
+            let found: Vec<_> = map.containing(&point).map(|(start, _, _)| *start).collect();
+            let brute_force: Vec<_> = expected.iter().filter(|&(&start, &end)| start <= point && point <= end).map(|(start, _)| *start).collect();
+            assert_eq!(found, brute_force, "point {point}");
+        }
+        drop(snapshot);
+    }
+}
+}
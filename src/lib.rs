@@ -1,129 +1,634 @@
-use std::marker::PhantomData;
-use std::mem::replace;
-use std::ops::{Index, IndexMut};
-use std::ptr::{self, NonNull};
+//! 朴素的 B 树和它的各种变体. 库本身是 `no_std` 的, 只依赖 `alloc`;
+//! 需要文件、IO 或者线程同步的部分 (PagedBtree, ConcurrentBtree, 二进制转储和 FrozenBtree) 在默认打开的 `std` feature 下提供.
+//!
+//! 所有的树都只通过 `Ord::cmp` 比较键. `Ord` 的实现不合法 (不满足传递性、和 `Eq` 不一致, 甚至每次返回随机的结果) 时也不会有未定义行为,
+//! 只是查找、插入、删除和遍历的结果不确定: 可能找不到已经插入的键、同一个键出现多次或者遍历的顺序错乱, 也可能 panic.
+//! 成员仍然只会被析构一次.
+
+#![no_std]
+
+extern crate alloc;
+#[cfg(any(test, feature = "std"))]
+extern crate std;
+
+use alloc::alloc::{handle_alloc_error, Layout};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::cell::Cell;
+use core::convert::Infallible;
+use core::fmt::{Debug, Write};
+use core::marker::PhantomData;
+use core::mem::{replace, MaybeUninit};
+use core::ops::{Bound, Index, IndexMut, RangeBounds};
+use core::ptr::{self, NonNull};
+use core::slice;
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+
+pub use allocator_api2::alloc::{Allocator, Global};
+
+use crate::error::{check_increasing, key_not_found, key_not_found_debug};
+use crate::inline_vec::InlineVec;
+use crate::rebalance::{rebalance, split, NodeAccess, Rebalance, Split, MIN_LEN};
+use crate::search::{bound_index, search_keys};
+
+pub mod arena;
+pub mod bplus;
+pub mod codec;
+#[cfg(feature = "std")]
+pub mod concurrent;
+#[cfg(feature = "std")]
+pub mod dump;
+mod error;
+#[cfg(feature = "std")]
+pub mod frozen;
+mod inline_vec;
+pub mod interval;
+#[cfg(feature = "std")]
+pub mod paged;
+pub mod persistent;
+mod rebalance;
+mod search;
+#[cfg(feature = "serde")]
+pub mod serde_support;
+pub mod transaction;
+#[cfg(feature = "std")]
+mod wal;
+
+pub use arena::ArenaBtree;
+pub use bplus::BplusTree;
+pub use codec::Codec;
+#[cfg(feature = "std")]
+pub use concurrent::ConcurrentBtree;
+#[cfg(feature = "std")]
+pub use dump::LoadError;
+pub use error::{AllocError, Error};
+#[cfg(feature = "std")]
+pub use frozen::FrozenBtree;
+pub use interval::IntervalMap;
+#[cfg(feature = "std")]
+pub use paged::{PagedBtree, Storage};
+pub use persistent::PersistentBtree;
+pub use transaction::Transaction;
 
 const RANK:usize = 5;
+/// 节点内定长数组的容量. 比 RANK - 1 多留一个位置, 用来放插入之后、分裂之前临时多出来的成员.
+const CAPACITY:usize = RANK;
+/// 树高的上限. 非根的内部节点至少有 RANK.div_ceil(2) 个子节点, 这个高度能容纳的成员数远远超过内存的大小.
+const MAX_HEIGHT:usize = 64;
+
+/// 叶子节点的布局, 同时也是内部节点的头部. 键和值分别存放在节点内的定长数组里, 只有前 len 个是初始化过的.
+/// 节点总是通过裸指针访问, 因为内部节点的指针会在 *mut Node 和 *mut InternalNode 之间转换,
+/// 经过 &Node 再转换回来的指针没有访问 children 的权限.
+///
+/// 节点可以被 Btree 和它的快照共享. 共享的节点只允许修改 parent 字段 (快照从不读取 parent),
+/// 其他字段在修改之前必须先复制一份.
+#[repr(C)]
 struct Node<K:Ord, V>
 {
-    members: Vec<(K,V)>,
-    children: Option<Vec<Box<Self>>>,
-    parent: Option<(*mut Self, usize)>,
+    parent: Option<(*mut InternalNode<K,V>, usize)>,
+    /// 指向本节点的父节点、树和快照的数量, 大于 1 表示节点被共享
+    refs: AtomicUsize,
+    len: usize,
+    /// 节点到叶子的距离, 叶子节点为 0. 节点的高度在它的整个生命周期内不会改变.
+    height: usize,
+    keys: [MaybeUninit<K>; CAPACITY],
+    vals: [MaybeUninit<V>; CAPACITY]
 }
+
+/// 内部节点的布局. 头部就是一个 Node, 所以指向内部节点的指针可以直接当作 *mut Node 使用.
+/// children 的前 len + 1 个是有效的子节点指针.
+#[repr(C)]
+struct InternalNode<K:Ord, V>
+{
+    data: Node<K,V>,
+    children: [*mut Node<K,V>; CAPACITY + 1]
+}
+
 enum SearchResult<K:Ord, V>
 {
     Found(*mut Node<K,V>, usize),
     NonFound(*mut Node<K,V>, usize)
 }
 
-fn box_as_mut_ptr<T>(b: &mut Box<T>) -> *mut T
+/// 从根节点向下查找时经过的路径, 每一项是一个内部节点和从它下降时走的子节点下标.
+/// 插入和删除沿着路径向上调整, 不需要读取 parent 指针.
+type Path<K,V> = InlineVec<(*mut Node<K,V>, usize), MAX_HEIGHT>;
+
+/// 插入或删除修改过的节点, 自底向上排列. 树的结构调整完之后再用 augment 依次更新它们,
+/// 这样 augment panic 时树已经是完整的.
+type Touched<K,V> = InlineVec<*mut Node<K,V>, { 2 * MAX_HEIGHT + 1 }>;
+
+/// 在数组前 len 个元素的 idx 位置插入 val, 后面的元素右移一位. 调用者保证数组还有空位.
+unsafe fn array_insert<T>(arr: *mut T, len: usize, idx: usize, val: T)
+{
+    unsafe {
+        ptr::copy(arr.add(idx), arr.add(idx + 1), len - idx);
+        arr.add(idx).write(val);
+    }
+}
+
+/// 取出数组前 len 个元素中 idx 位置的元素, 后面的元素左移一位.
+unsafe fn array_remove<T>(arr: *mut T, len: usize, idx: usize) -> T
 {
-    ptr::from_mut(b.as_mut())
+    unsafe {
+        let val = arr.add(idx).read();
+        ptr::copy(arr.add(idx + 1), arr.add(idx), len - idx - 1);
+        val
+    }
 }
 
 impl<K:Ord, V> Node<K,V>
 {
-    fn search(this: &Self, key: &K) -> SearchResult<K,V>
+    fn layout(height: usize) -> Layout
     {
-        let index = match this.members.iter().position(|(k,_)| k >= key )
-        {
-            None => this.members.len(),
-            Some(idx) if this.members[idx].0 == *key => return SearchResult::Found(ptr::from_ref(this) as *mut Self, idx),
-            Some(idx) => idx
+        if height == 0 { Layout::new::<Self>() } else { Layout::new::<InternalNode<K,V>>() }
+    }
+
+    /// 用 alloc 分配一个空节点, height 为 0 时是叶子节点
+    fn try_new<A: Allocator>(height: usize, alloc: &A) -> Result<*mut Self, AllocError>
+    {
+        let header = Self{
+            parent: None,
+            refs: AtomicUsize::new(1),
+            len: 0,
+            height,
+            keys: [const { MaybeUninit::uninit() }; CAPACITY],
+            vals: [const { MaybeUninit::uninit() }; CAPACITY]
         };
+        unsafe {
+            let this = alloc.allocate(Self::layout(height)).map_err(|_| AllocError)?.cast::<Self>().as_ptr();
+            if height == 0 { this.write(header) }
+            else { this.cast::<InternalNode<K,V>>().write(InternalNode{ data: header, children: [ptr::null_mut(); CAPACITY + 1] }) }
+            Ok(this)
+        }
+    }
 
-        match this.children
-        {
-            None => SearchResult::NonFound(ptr::from_ref(this) as *mut Self, index),
-            Some(ref children) => Self::search(&children[index], key)
+    /// 和 try_new 一样, 内存不足时中止程序
+    fn new<A: Allocator>(height: usize, alloc: &A) -> *mut Self
+    {
+        Self::try_new(height, alloc).unwrap_or_else(|_| handle_alloc_error(Self::layout(height)))
+    }
+
+    /// 释放节点本身占用的内存, 不会释放成员和子节点. alloc 必须是分配节点时使用的分配器.
+    unsafe fn dealloc<A: Allocator>(this: *mut Self, alloc: &A)
+    {
+        unsafe { alloc.deallocate(NonNull::new_unchecked(this.cast()), Self::layout((*this).height)) }
+    }
+
+    unsafe fn key_ptr(this: *mut Self) -> *mut K
+    {
+        unsafe { (&raw mut (*this).keys).cast() }
+    }
+
+    unsafe fn val_ptr(this: *mut Self) -> *mut V
+    {
+        unsafe { (&raw mut (*this).vals).cast() }
+    }
+
+    /// 内部节点的子节点数组, 调用者保证 this 是内部节点
+    unsafe fn child_ptr(this: *mut Self) -> *mut *mut Self
+    {
+        unsafe { (&raw mut (*this.cast::<InternalNode<K,V>>()).children).cast() }
+    }
+
+    unsafe fn keys<'a>(this: *const Self) -> &'a [K]
+    {
+        unsafe { slice::from_raw_parts((&raw const (*this).keys).cast(), (*this).len) }
+    }
+
+    unsafe fn vals<'a>(this: *const Self) -> &'a [V]
+    {
+        unsafe { slice::from_raw_parts((&raw const (*this).vals).cast(), (*this).len) }
+    }
+
+    /// 内部节点的 len + 1 个子节点, 叶子节点返回空切片
+    unsafe fn children<'a>(this: *const Self) -> &'a [*mut Self]
+    {
+        unsafe {
+            if (*this).height == 0 { &[] }
+            else { &(&(*this.cast::<InternalNode<K,V>>()).children)[..(*this).len + 1] }
         }
     }
 
-    /// 传入一个插入目标节点的引用, 如果不产生新的根节点则返回 None, 如果有新的跟节点, 则返回新根节点的 Box 指针.
-    fn insert(this: &mut Self, index: usize, key: K, value: V) -> Option<Box<Self>>
+    unsafe fn insert_member(this: *mut Self, index: usize, key: K, value: V)
     {
-        this.members.insert(index, (key,value));
+        unsafe {
+            array_insert(Self::key_ptr(this), (*this).len, index, key);
+            array_insert(Self::val_ptr(this), (*this).len, index, value);
+            (*this).len += 1;
+        }
+    }
 
-        if this.members.len() < RANK { None }
-        else {
-            let right_members = this.members.split_off((RANK + 1) / 2);
-            let mid_member = this.members.pop().unwrap();
+    unsafe fn remove_member(this: *mut Self, index: usize) -> (K,V)
+    {
+        unsafe {
+            let key = array_remove(Self::key_ptr(this), (*this).len, index);
+            let value = array_remove(Self::val_ptr(this), (*this).len, index);
+            (*this).len -= 1;
+            (key, value)
+        }
+    }
 
-            let mut new_right_node = Box::new(Self{
-                members: right_members,
-                children: None,
-                parent: None
-            });
+    unsafe fn replace_member(this: *mut Self, index: usize, key: K, value: V) -> (K,V)
+    {
+        unsafe {
+            (replace(&mut *Self::key_ptr(this).add(index), key), replace(&mut *Self::val_ptr(this).add(index), value))
+        }
+    }
 
-            if let Some(ref mut children) = this.children {
-                let mut right_children = children.split_off((RANK + 1) / 2);
+    /// 把 children[from ..= len] 的 parent 字段改为指向 this 和各自的下标
+    unsafe fn fix_children_parent(this: *mut Self, from: usize)
+    {
+        unsafe {
+            for i in from ..= (*this).len {
+                (**Self::child_ptr(this).add(i)).parent = Some((this.cast(), i));
+            }
+        }
+    }
 
-                right_children.iter_mut().enumerate().for_each(|(i,child)| {
-                    child.parent = Some((box_as_mut_ptr(&mut new_right_node), i))
-                });
+    /// 一直沿着第一个子节点往下走, 得到子树中最左边的叶子节点
+    unsafe fn first_leaf(this: *mut Self) -> *mut Self
+    {
+        let mut ptr = this;
+        while unsafe { (*ptr).height } > 0 {
+            ptr = unsafe { *Self::child_ptr(ptr) };
+        }
+        ptr
+    }
 
-                new_right_node.children = Some(right_children);
+    unsafe fn search(this: *mut Self, key: &K) -> SearchResult<K,V>
+    {
+        let mut node = this;
+        loop {
+            match search_keys(unsafe { Self::keys(node) }, key)
+            {
+                Ok(idx) => return SearchResult::Found(node, idx),
+                Err(idx) if unsafe { (*node).height } == 0 => return SearchResult::NonFound(node, idx),
+                Err(idx) => node = unsafe { Self::children(node)[idx] }
             }
+        }
+    }
+
+    /// 依次用 augment 更新 this 和 path 中它的祖先
+    unsafe fn augment_path(this: *mut Self, path: &Path<K,V>, augment: Option<Augment<K,V>>)
+    {
+        let Some(augment) = augment else { return };
+        unsafe {
+            augment(this);
+            path.iter().rev().for_each(|&(ancestor, _)| augment(ancestor));
+        }
+    }
+
+    /// 减少节点的引用计数, 归零时释放节点的成员, 并对所有子节点做同样的事. 释放过程是循环, 不会随树高递归.
+    /// 成员的析构函数 panic 时, 剩下的成员仍然会被析构, 所有节点仍然会被释放, 然后继续展开; 展开中再次 panic 会中止程序.
+    unsafe fn release<A: Allocator>(this: *mut Self, alloc: &A)
+    {
+        unsafe {
+            if (*this).refs.fetch_sub(1, Ordering::Release) != 1 { return }
+            fence(Ordering::Acquire);
 
-            match this.parent
+            let mut teardown = Teardown{ stack: InlineVec::new(), alloc };
+            teardown.stack.push((this, 0));
+            // 某一步 panic 时 guard 在展开过程中完成剩下的步骤
+            struct Guard<'r, 'a, K:Ord, V, A: Allocator>(&'r mut Teardown<'a, K,V,A>);
+            impl<K:Ord, V, A: Allocator> Drop for Guard<'_, '_, K,V,A>
             {
-                None => {
-                    let new_right_node_parent = ptr::from_mut(&mut new_right_node.parent); 
-                    // 绕过借用检查, 实现环形引用, 但是因为 parent 的 *mut Self 本质上只是简单的整数, 其类型是指针, 
-                    // 没有实现 Drop trait, 因此在 Node 释放的时候不会去释放 parent 所指向的地址
-
-                    let mut new_root_node = Box::new(Self{
-                        members: vec![mid_member],
-                        children: Some(vec![ unsafe{ Box::from_raw(ptr::from_mut(this)) }, new_right_node]),
-                        parent: None
-                    });
-                    unsafe { *new_right_node_parent  = Some((box_as_mut_ptr(&mut new_root_node), 1)); }
-                    this.parent = Some((box_as_mut_ptr(&mut new_root_node), 0));
-                    
-                    Some(new_root_node)
+                fn drop(&mut self) {
+                    while unsafe { self.0.step() } {}
                 }
-                Some((parent,parent_idx)) => {
-                    new_right_node.parent = Some((parent, parent_idx + 1));
+            }
+            let guard = Guard(&mut teardown);
+            while guard.0.step() {}
+            core::mem::forget(guard);
+        }
+    }
+
+    /// 确保 this 的第 idx 个子节点没有被共享, 被共享时用复制出来的节点替换它. 返回替换之后的子节点.
+    unsafe fn try_unique_child<A: Allocator>(this: *mut Self, idx: usize, cloner: Option<Cloner<K,V,A>>, alloc: &A) -> Result<*mut Self, AllocError>
+    {
+        unsafe {
+            let slot = Self::child_ptr(this).add(idx);
+            let child = *slot;
+            if (*child).refs.load(Ordering::Acquire) > 1 {
+                let copy = cloner.expect("被共享的节点必须能够复制")(child, alloc)?;
+                (*copy).parent = Some((this.cast(), idx));
+                *slot = copy;
+                // 读到引用计数之后其他线程可能已经释放了快照, 这时 this 是最后一个持有者, 由它释放原来的节点
+                Self::release(child, alloc);
+            }
+            Ok(*slot)
+        }
+    }
 
-                    unsafe{ 
-                        (*parent).children.as_mut().unwrap()[parent_idx + 1 ..].iter_mut().for_each(|child|
-                            child.parent.as_mut().unwrap().1 += 1
-                        );
-                        (*parent).children.as_mut().unwrap().insert(parent_idx + 1, new_right_node);
+    unsafe fn unique_child<A: Allocator>(this: *mut Self, idx: usize, cloner: Option<Cloner<K,V,A>>, alloc: &A) -> *mut Self
+    {
+        unsafe { Self::try_unique_child(this, idx, cloner, alloc).unwrap_or_else(|_| handle_alloc_error(Self::layout((*this).height))) }
+    }
+
+    /// 为在叶子节点 leaf 中插入一个成员预先分配分裂需要的节点: 从 leaf 沿着 path 向上每个满的节点需要一个新的右节点,
+    /// 根节点也满时还需要一个新的根节点. 返回的节点按高度从大到小排列, 可以依次 pop 出来使用.
+    unsafe fn alloc_split_nodes<A: Allocator>(leaf: *mut Self, path: &Path<K,V>, alloc: &A) -> Result<Vec<*mut Self>, AllocError>
+    {
+        unsafe {
+            let mut count = 0;
+            let mut node = leaf;
+            let mut ancestors = path.iter().rev();
+            while (*node).len == RANK - 1 {
+                count += 1;
+                match ancestors.next()
+                {
+                    Some(&(parent, _)) => node = parent,
+                    None => { count += 1; break }
+                }
+            }
 
-                        Self::insert(parent.as_mut().unwrap(), parent_idx, mid_member.0, mid_member.1)
+            // 第 i 次分裂的节点高度是 i, 新的根节点的高度等于分裂的次数
+            let mut nodes = Vec::new();
+            nodes.try_reserve_exact(count).map_err(|_| AllocError)?;
+            for height in (0..count).rev() {
+                match Self::try_new(height, alloc)
+                {
+                    Ok(node) => nodes.push(node),
+                    Err(err) => {
+                        nodes.into_iter().for_each(|node| Self::dealloc(node, alloc));
+                        return Err(err);
                     }
                 }
             }
+            Ok(nodes)
+        }
+    }
+
+    /// 为删除 this 的第 index 个成员预先复制会被修改的共享节点: 后继成员所在的路径, 以及可能借用或者合并的兄弟节点.
+    /// path 是从根节点到 this 的父节点的路径, 上面的节点都不是共享的. 之后的 remove 不再需要复制节点.
+    unsafe fn try_unique_for_remove<A: Allocator>(this: *mut Self, index: usize, path: &Path<K,V>, cloner: Option<Cloner<K,V,A>>, alloc: &A)
+        -> Result<(), AllocError>
+    {
+        if cloner.is_none() { return Ok(()) }
+        unsafe {
+            let mut full_path = Path::new();
+            path.iter().for_each(|&step| full_path.push(step));
+            let mut leaf = this;
+            if (*this).height > 0 {
+                full_path.push((this, index + 1));
+                leaf = Self::try_unique_child(this, index + 1, cloner, alloc)?;
+                while (*leaf).height > 0 {
+                    full_path.push((leaf, 0));
+                    leaf = Self::try_unique_child(leaf, 0, cloner, alloc)?;
+                }
+            }
+
+            // 删除之后少于 MIN_LEN 个成员的节点会向兄弟借用或者和兄弟合并, 合并又会让父节点少一个成员
+            let mut len = (*leaf).len - 1;
+            for &(parent, idx) in full_path.iter().rev() {
+                if len >= MIN_LEN { break }
+                if idx < (*parent).len { Self::try_unique_child(parent, idx + 1, cloner, alloc)?; }
+                if idx > 0 { Self::try_unique_child(parent, idx - 1, cloner, alloc)?; }
+                len = (*parent).len - 1;
+            }
+            Ok(())
+        }
+    }
+
+    /// 让整棵子树都不再被共享
+    unsafe fn unique_subtree<A: Allocator>(this: *mut Self, cloner: Option<Cloner<K,V,A>>, alloc: &A)
+    {
+        unsafe {
+            for idx in 0 .. Self::children(this).len() {
+                Self::unique_subtree(Self::unique_child(this, idx, cloner, alloc), cloner, alloc);
+            }
+        }
+    }
+}
+
+/// 不递归、不分配内存地释放一棵子树. 栈中每一帧是一个引用计数已经归零的节点和它进行到的步骤:
+/// 依次是释放每个子节点、析构所有键、析构所有值、释放节点本身的内存. 子节点的引用计数归零时压栈, 所以栈的深度不超过树高.
+struct Teardown<'a, K:Ord, V, A: Allocator>
+{
+    stack: InlineVec<(*mut Node<K,V>, usize), MAX_HEIGHT>,
+    alloc: &'a A
+}
+
+impl<K:Ord, V, A: Allocator> Teardown<'_, K,V,A>
+{
+    /// 执行栈顶节点的下一步, 全部完成时返回 false. 先推进步骤再执行, 这一步 panic 之后不会被重复执行.
+    unsafe fn step(&mut self) -> bool
+    {
+        unsafe {
+            let Some((node, step)) = self.stack.last_mut() else { return false };
+            let (node, current) = (*node, *step);
+            *step += 1;
+            let children = Node::children(node).len();
+            if current < children {
+                let child = Node::children(node)[current];
+                if (*child).refs.fetch_sub(1, Ordering::Release) == 1 {
+                    fence(Ordering::Acquire);
+                    self.stack.push((child, 0));
+                }
+            }
+            else if current == children { ptr::drop_in_place(ptr::slice_from_raw_parts_mut(Node::key_ptr(node), (*node).len)) }
+            else if current == children + 1 { ptr::drop_in_place(ptr::slice_from_raw_parts_mut(Node::val_ptr(node), (*node).len)) }
+            else {
+                self.stack.pop();
+                Node::dealloc(node, self.alloc);
+            }
+            true
+        }
+    }
+}
+
+/// 复制一个节点的函数, 新节点的内存分配失败时返回 AllocError. 只有调用过 Btree::snapshot 的树才会有共享的节点, 那时 K 和 V 一定实现了 Clone,
+/// 所以在 snapshot 中把对应的 Node::clone_node 记录下来, 不需要给 Btree 的其他方法加上 Clone 约束.
+type Cloner<K,V,A> = unsafe fn(*mut Node<K,V>, &A) -> Result<*mut Node<K,V>, AllocError>;
+
+/// 节点的成员或者子节点变化之后, 重新计算节点附加在值里的信息的函数. 调用时子节点的信息已经是最新的.
+/// IntervalMap 用它维护子树中区间终点的最大值.
+type Augment<K,V> = unsafe fn(*mut Node<K,V>);
+
+impl<K:Ord + Clone, V: Clone> Node<K,V>
+{
+    /// 复制节点的成员, 子节点只增加引用计数, 并把子节点的 parent 改为指向新节点. 新节点的引用计数为 1.
+    /// clone panic 时析构已经复制的成员并释放新节点, 原来的节点不受影响.
+    unsafe fn clone_node<A: Allocator>(this: *mut Self, alloc: &A) -> Result<*mut Self, AllocError>
+    {
+        struct Guard<'a, K:Ord, V, A: Allocator>(*mut Node<K,V>, &'a A);
+        impl<K:Ord, V, A: Allocator> Drop for Guard<'_, K,V,A>
+        {
+            fn drop(&mut self) {
+                unsafe {
+                    ptr::drop_in_place(ptr::slice_from_raw_parts_mut(Node::key_ptr(self.0), (*self.0).len));
+                    ptr::drop_in_place(ptr::slice_from_raw_parts_mut(Node::val_ptr(self.0), (*self.0).len));
+                    Node::dealloc(self.0, self.1);
+                }
+            }
+        }
+
+        unsafe {
+            let height = (*this).height;
+            let copy = Self::try_new(height, alloc)?;
+            let guard = Guard(copy, alloc);
+            for (i, (key, value)) in Self::keys(this).iter().zip(Self::vals(this)).enumerate() {
+                let key = key.clone();
+                let value = value.clone();
+                Self::key_ptr(copy).add(i).write(key);
+                Self::val_ptr(copy).add(i).write(value);
+                (*copy).len = i + 1;
+            }
+            core::mem::forget(guard);
+            (*copy).parent = (*this).parent;
+
+            if height > 0 {
+                ptr::copy_nonoverlapping(Self::child_ptr(this), Self::child_ptr(copy), (*this).len + 1);
+                for &child in Self::children(copy) {
+                    (*child).refs.fetch_add(1, Ordering::Relaxed);
+                }
+                Self::fix_children_parent(copy, 0);
+            }
+            Ok(copy)
         }
     }
 }
 
-pub struct Btree<K:Ord, V>
+/// 树从创建以来做过的结构调整次数
+#[derive(Debug, Clone, Copy, Default)]
+struct OpCounters
+{
+    splits: u64,
+    merges: u64,
+    borrows: u64
+}
+
+/// B 树. 所有节点都由分配器 A 分配, 默认使用全局分配器.
+///
+/// # Panic 安全
+///
+/// - 插入、删除和查找只在修改树之前调用 `Ord`, 比较 panic 时树保持原样.
+/// - 修改快照共享的节点前调用 `Clone` 复制节点, clone panic 时树保持原样, 已经复制的成员会被析构.
+/// - 析构树或者快照时某个键或者值的析构函数 panic, 其余成员仍然会被析构, 所有节点都会被释放, 不会泄漏也不会重复析构.
+/// - 迭代器只借用树, 在中途丢弃不影响树.
+///
+/// # 内存分配失败
+///
+/// try_new、try_new_in、try_insert、try_remove 和 try_get_mut 在节点的内存分配失败时返回错误, 树的内容保持不变.
+/// 其他需要分配节点的方法 (包括有快照时复制共享节点的 remove、get_mut 和 iter_mut) 在分配失败时中止程序.
+pub struct Btree<K:Ord, V, A: Allocator = Global>
 {
-    root: *mut Node<K,V>
+    root: *mut Node<K,V>,
+    counters: OpCounters,
+    /// 第一次创建快照时设置, 之后修改被共享的节点前用它复制节点
+    cloner: Cell<Option<Cloner<K,V,A>>>,
+    /// 只有 IntervalMap 内部的树设置, 由 insert、try_insert、remove 和 try_remove 维护
+    augment: Option<Augment<K,V>>,
+    alloc: A
 }
 
 impl<K:Ord, V> Btree<K,V>
 {
     pub fn new() -> Self
     {
-        Self
+        Self::new_in(Global)
+    }
+
+    /// 和 new 一样, 但是根节点的内存分配失败时返回 AllocError
+    pub fn try_new() -> Result<Self, AllocError>
+    {
+        Self::try_new_in(Global)
+    }
+
+    /// 每次修改之后都用 augment 更新被修改的节点和它们的祖先的树
+    pub(crate) fn new_augmented(augment: Augment<K,V>) -> Self
+    {
+        let mut tree = Self::new();
+        tree.augment = Some(augment);
+        tree
+    }
+}
+
+impl<K:Ord, V, A: Allocator> Btree<K,V,A>
+{
+    /// 创建一棵所有节点都由 alloc 分配的空树
+    pub fn new_in(alloc: A) -> Self
+    {
+        Self::try_new_in(alloc).unwrap_or_else(|_| handle_alloc_error(Layout::new::<Node<K,V>>()))
+    }
+
+    pub fn try_new_in(alloc: A) -> Result<Self, AllocError>
+    {
+        Ok(Self
         {
-            root: Box::into_raw(Box::new(Node{
-                members: Vec::new(),
-                children: None,
-                parent: None
-            }))
+            root: Node::try_new(0, &alloc)?,
+            counters: OpCounters::default(),
+            cloner: Cell::new(None),
+            augment: None,
+            alloc
+        })
+    }
+
+    pub fn allocator(&self) -> &A
+    {
+        &self.alloc
+    }
+
+    /// 删除所有成员. 和析构一样循环释放节点, 被快照共享的节点只减少引用计数.
+    /// 先换上新的空根节点再释放旧的树, 析构函数 panic 时树已经是空的.
+    pub fn clear(&mut self)
+    {
+        let old_root = replace(&mut self.root, Node::new(0, &self.alloc));
+        unsafe { Node::release(old_root, &self.alloc) };
+    }
+
+    /// 根节点被快照共享时复制一份作为新的根节点
+    unsafe fn try_unique_root(&mut self) -> Result<(), AllocError>
+    {
+        unsafe {
+            if (*self.root).refs.load(Ordering::Acquire) > 1 {
+                let copy = self.cloner.get().expect("被共享的节点必须能够复制")(self.root, &self.alloc)?;
+                Node::release(replace(&mut self.root, copy), &self.alloc);
+            }
+            Ok(())
         }
     }
 
+    unsafe fn unique_root(&mut self)
+    {
+        unsafe { self.try_unique_root().unwrap_or_else(|_| handle_alloc_error(Node::<K,V>::layout((*self.root).height))) }
+    }
+
+    /// 和 Node::search 一样查找, 但是会复制路径上被快照共享的节点, 返回之后可以直接修改路径上的节点.
+    /// 经过的内部节点记录在 path 中. 复制到一半失败时已经复制的节点和原来的内容相同, 树仍然是完整的.
+    unsafe fn try_search_mut(&mut self, key: &K, path: &mut Path<K,V>) -> Result<SearchResult<K,V>, AllocError>
+    {
+        unsafe {
+            self.try_unique_root()?;
+            let cloner = self.cloner.get();
+            let mut node = self.root;
+            loop {
+                match search_keys(Node::keys(node), key)
+                {
+                    Ok(idx) => return Ok(SearchResult::Found(node, idx)),
+                    Err(idx) if (*node).height == 0 => return Ok(SearchResult::NonFound(node, idx)),
+                    Err(idx) => {
+                        path.push((node, idx));
+                        node = Node::try_unique_child(node, idx, cloner, &self.alloc)?;
+                    }
+                }
+            }
+        }
+    }
+
+    unsafe fn search_mut(&mut self, key: &K, path: &mut Path<K,V>) -> SearchResult<K,V>
+    {
+        unsafe { self.try_search_mut(key, path).unwrap_or_else(|_| handle_alloc_error(Node::<K,V>::layout(1))) }
+    }
+
     pub fn get(&self, key: &K) -> Option<&V>
     {
         unsafe{
-            match Node::search(self.root.as_ref().unwrap(), key)
+            match Node::search(self.root, key)
             {
-                SearchResult::Found(p, idx) => Some(&(*p).members[idx].1),
+                SearchResult::Found(p, idx) => Some(&*Node::val_ptr(p).add(idx)),
                 SearchResult::NonFound(_, _) => None
             }
         }
@@ -132,43 +637,174 @@ impl<K:Ord, V> Btree<K,V>
     pub fn get_mut(&mut self, key: &K) -> Option<&mut V>
     {
         unsafe{
-            match Node::search(self.root.as_ref().unwrap(), key)
+            match self.search_mut(key, &mut Path::new())
             {
-                SearchResult::Found(p, idx) => Some(&mut (*p).members[idx].1),
+                SearchResult::Found(p, idx) => Some(&mut *Node::val_ptr(p).add(idx)),
                 SearchResult::NonFound(_, _) => None
             }
         }
     }
 
+    /// 插入或删除完成之后用 augment 自底向上更新被修改的节点
+    fn augment_touched(&self, touched: &Touched<K,V>)
+    {
+        let Some(augment) = self.augment else { return };
+        touched.iter().for_each(|&node| unsafe { augment(node) });
+    }
+
     pub fn insert(&mut self, key: K, value: V) -> Option<V>
     {
         unsafe{
-            match Node::search(self.root.as_ref().unwrap(), &key)
+            let mut path = Path::new();
+            match self.search_mut(&key, &mut path)
             {
-                SearchResult::Found(p, idx) => Some(replace(&mut (*p).members[idx].1, value)),
+                SearchResult::Found(p, idx) => {
+                    let old = replace(&mut *Node::val_ptr(p).add(idx), value);
+                    Node::augment_path(p, &path, self.augment);
+                    Some(old)
+                }
                 SearchResult::NonFound(p, idx) => {
-                    if let Some(new_root) = Node::insert(p.as_mut().unwrap(), idx, key, value) {
-                        self.root = Box::into_raw(new_root);
+                    let mut touched = Touched::new();
+                    let mut new_node = |height| Node::new(height, &self.alloc);
+                    Node::insert_member(p, idx, key, value);
+                    let mut splitter = Splitter{ counters: &mut self.counters, touched: &mut touched, new_node: &mut new_node };
+                    let Ok(new_root) = split(&mut splitter, p, &path);
+                    if let Some(new_root) = new_root {
+                        self.root = new_root;
                     }
+                    self.augment_touched(&touched);
                     None
                 }
             }
         }
     }
+
+    /// 和 insert 一样, 但是节点的内存分配失败时返回 AllocError, 树保持不变, key 和 value 被丢弃.
+    /// 分裂需要的节点在修改树之前全部分配好, 所以不会留下分裂到一半的树.
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, AllocError>
+    {
+        unsafe{
+            let mut path = Path::new();
+            match self.try_search_mut(&key, &mut path)?
+            {
+                SearchResult::Found(p, idx) => {
+                    let old = replace(&mut *Node::val_ptr(p).add(idx), value);
+                    Node::augment_path(p, &path, self.augment);
+                    Ok(Some(old))
+                }
+                SearchResult::NonFound(p, idx) => {
+                    let mut nodes = Node::alloc_split_nodes(p, &path, &self.alloc)?;
+                    let mut new_node = |height| {
+                        let node = nodes.pop().expect("预先分配的节点不够");
+                        debug_assert_eq!((*node).height, height);
+                        node
+                    };
+                    let mut touched = Touched::new();
+                    Node::insert_member(p, idx, key, value);
+                    let mut splitter = Splitter{ counters: &mut self.counters, touched: &mut touched, new_node: &mut new_node };
+                    let Ok(new_root) = split(&mut splitter, p, &path);
+                    if let Some(new_root) = new_root {
+                        self.root = new_root;
+                    }
+                    debug_assert!(nodes.is_empty());
+                    self.augment_touched(&touched);
+                    Ok(None)
+                }
+            }
+        }
+    }
+}
+
+impl<K:Ord, V> Node<K,V>
+{
+    /// 自底向上构建时, 把一层的子节点和它们之间的分隔成员分组成上一层的节点.
+    /// height 为 0 时构建叶子, children 为空. 返回新的节点和新节点之间的分隔成员.
+    unsafe fn build_level<A: Allocator>(height: usize, children: Vec<*mut Self>, members: Vec<(K,V)>, alloc: &A) -> (Vec<*mut Self>, Vec<(K,V)>)
+    {
+        unsafe {
+            // 每个节点占 size 个位置: size 个子节点和 size - 1 个成员, 节点之间还有一个成员留给上一层.
+            // 平均分配之后每个节点都至少半满
+            let slots = members.len() + 1;
+            let count = slots.div_ceil(RANK);
+            let mut members = members.into_iter();
+            let mut children = children.into_iter();
+            let mut nodes = Vec::with_capacity(count);
+            let mut separators = Vec::with_capacity(count - 1);
+
+            for i in 0..count {
+                let size = slots / count + usize::from(i < slots % count);
+                let node = Self::new(height, alloc);
+                for (idx, (key, value)) in members.by_ref().take(size - 1).enumerate() {
+                    Self::key_ptr(node).add(idx).write(key);
+                    Self::val_ptr(node).add(idx).write(value);
+                    (*node).len = idx + 1;
+                }
+                if height > 0 {
+                    for (idx, child) in children.by_ref().take(size).enumerate() {
+                        *Self::child_ptr(node).add(idx) = child;
+                    }
+                    Self::fix_children_parent(node, 0);
+                }
+                nodes.push(node);
+                separators.extend(members.next());
+            }
+            (nodes, separators)
+        }
+    }
+}
+
+impl<K:Ord, V> Btree<K,V>
+{
+    /// 由键严格递增的键值对自底向上直接构建树, 不需要逐个插入时的查找和分裂. 键不是严格递增时 panic.
+    pub fn from_sorted_iter(iter: impl IntoIterator<Item = (K,V)>) -> Self
+    {
+        Self::try_from_sorted_iter(iter).unwrap_or_else(|err| panic!("键必须严格递增: {err}"))
+    }
+
+    /// 和 from_sorted_iter 一样, 键重复时返回 Error::DuplicateKey, 键不是递增时返回 Error::UnsortedKeys
+    pub fn try_from_sorted_iter(iter: impl IntoIterator<Item = (K,V)>) -> Result<Self, Error>
+    {
+        let entries: Vec<_> = iter.into_iter().collect();
+        entries.windows(2).try_for_each(|pair| check_increasing(&pair[0].0, &pair[1].0))?;
+        Ok(Self::build_sorted(entries))
+    }
+
+    /// 调用者保证 entries 的键严格递增
+    pub(crate) fn build_sorted(entries: Vec<(K,V)>) -> Self
+    {
+        if entries.is_empty() { return Self::new() }
+        unsafe {
+            let (mut nodes, mut members) = Node::build_level(0, Vec::new(), entries, &Global);
+            let mut height = 0;
+            while nodes.len() > 1 {
+                height += 1;
+                (nodes, members) = Node::build_level(height, nodes, members, &Global);
+            }
+            Self{ root: nodes[0], counters: OpCounters::default(), cloner: Cell::new(None), augment: None, alloc: Global }
+        }
+    }
+}
+
+impl<K:Ord, V> Default for Btree<K,V>
+{
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl<K:Ord, V> Drop for Btree<K,V>
+impl<K:Ord, V, A: Allocator> Drop for Btree<K,V,A>
 {
     fn drop(&mut self) {
-        unsafe { drop(Box::from_raw(self.root)) };
+        unsafe { Node::release(self.root, &self.alloc) };
     }
 }
 
 pub struct Iter<'a, K: Ord, V>
 {
-    current_node: &'a Node<K,V>,
+    current_node: NonNull<Node<K,V>>,
     idx: usize,
-    is_first: bool
+    is_first: bool,
+    _marker: PhantomData<&'a Node<K,V>>
 }
 
 impl<K:Ord, V> Node<K,V> {
@@ -176,263 +812,438 @@ impl<K:Ord, V> Node<K,V> {
     /// is_child_index 表示下标是否是 children 数组的下标.
     unsafe fn get_next(this: *mut Self, index: usize, is_child_index: bool) -> Option<(*mut Self, usize)>
     {
-        if is_child_index {
-            if index < unsafe { (*this).members.len() }  { Some((this, index)) }
-            else {
-                match unsafe {(*this).parent } {
-                    None => None, 
-                    Some((parent, index)) => unsafe { Self::get_next( parent, index, true) }
-                }
+        unsafe {
+            let (mut node, mut index) = (this, index);
+            if !is_child_index {
+                if (*node).height > 0 { return Some((Self::first_leaf(Self::children(node)[index + 1]), 0)) }
+                index += 1;
             }
-        }
-        else {
-            unsafe {
-                match &mut (*this).children {
-                    None => if index + 1 < (*this).members.len() { Some((this, index + 1)) }
-                            else { Self::get_next(this, index + 1, true) }
-                    Some(children) => {
-                        let mut ptr = &mut children[index + 1];
-                        while let Some(ref mut child) = (*ptr).children {
-                            ptr = &mut child[0];
-                        }
-
-                        Some((box_as_mut_ptr(ptr), 0))
-                    }
-                }
+            // 子树已经遍历完, 沿着 parent 向上找到第一个还有成员没有遍历的祖先
+            while index >= (*node).len {
+                let (parent, parent_idx) = (*node).parent?;
+                (node, index) = (parent.cast(), parent_idx);
             }
+            Some((node, index))
         }
     }
 }
 
 impl<'a, K:Ord, V> Iterator for Iter<'a, K,V>
 {
-    type Item = &'a (K,V);
+    type Item = (&'a K, &'a V);
 
     fn next(&mut self) -> Option<Self::Item>
     {
+        let node = self.current_node.as_ptr();
         if self.is_first {
             self.is_first = false;
-            if self.current_node.members.is_empty() { None }
-            else { Some( &self.current_node.members[self.idx] ) }
+            if unsafe { (*node).len } == 0 { None }
+            else { Some( unsafe { (&Node::keys(node)[self.idx], &Node::vals(node)[self.idx]) } ) }
         }
         else {
-            match unsafe { Node::get_next(ptr::from_ref(self.current_node) as *mut Node<K,V>, self.idx, false) }
+            match unsafe { Node::get_next(node, self.idx, false) }
             {
                 None => None,
                 Some((pt,i)) => {
-                    self.current_node = unsafe { pt.as_ref().unwrap() };
+                    self.current_node = NonNull::new(pt).unwrap();
                     self.idx = i;
-                    Some( &self.current_node.members[i] )
+                    Some( unsafe { (&Node::keys(pt)[i], &Node::vals(pt)[i]) } )
                 }
             }
         }
     }
 }
 
-impl<'a, K:Ord, V> Btree<K,V> {
-    pub fn iter(&self) -> Iter<'a, K,V>
+impl<K:Ord, V, A: Allocator> Btree<K,V,A> {
+    pub fn iter(&self) -> Iter<'_, K,V>
     {
-        match unsafe{ &mut (*self.root).children }
-        {
-            None => Iter{ current_node: unsafe { self.root.as_ref().unwrap() }, idx:0,  is_first: true },
-            Some(children) => {
-                let mut ptr = &mut children[0];
-                while let Some(ref mut child) = (*ptr).children {
-                    ptr = &mut child[0];
-                }
-
-                Iter{ current_node: (*ptr).as_ref() , idx: 0, is_first: true }
-            }
-        }
+        let leaf = unsafe { Node::first_leaf(self.root) };
+        Iter{ current_node: NonNull::new(leaf).unwrap(), idx: 0, is_first: true, _marker: PhantomData }
     }
 }
 
-
-impl<K: Ord, V> Node<K,V>
-{
-    /// 从兄弟节点移动成员到本节点, origin 是 true 表示右边节点减少成员, origin 是 false 表示左边节点减少成员. 本函数不检查左边或者右边是否有兄弟节点.
-    fn get_from_sibling(&mut self, origin: bool)
+impl<K:Ord, V> Node<K,V> {
+    /// 返回第一个大于等于 key (inclusive 为 false 时是大于 key) 的成员位置, 没有这样的成员则返回 None.
+    unsafe fn lower_bound(this: *mut Self, key: &K, inclusive: bool) -> Option<(*mut Self, usize)>
     {
-        let (parent, parent_idx)  = unsafe { 
-            let (a,b) = self.parent.expect("必须要有父节点");
-            (a.as_mut().unwrap(), b)
-        };
-        if origin {
-            let right_sibling = &mut parent.children.as_mut().unwrap()[parent_idx + 1];
-            let new_mid_member = right_sibling.members.remove(0); //提取右兄弟的第一个成员
-            let new_member = replace(&mut parent.members[parent_idx], new_mid_member);
-            self.members.push(new_member);
-
-            if let Some(ref mut right_children) = right_sibling.children {
-                let mut child = right_children.remove(0);
-                right_children.iter_mut().for_each(|item| item.parent.as_mut().unwrap().1 -= 1);
-
-                child.parent = Some((ptr::from_mut(self), self.members.len()));
-                self.children.as_mut().unwrap().push(child);
-            }
-        }
-        else {
-            let left_sibling = &mut parent.children.as_mut().unwrap()[parent_idx - 1];
-            let new_mid_member = left_sibling.members.pop().unwrap();
-            let new_member = replace(&mut parent.members[parent_idx -1], new_mid_member);
-            self.members.insert(0, new_member);
+        unsafe {
+            let mut node = this;
+            loop {
+                let keys = Self::keys(node);
+                let index = bound_index(keys, key, inclusive);
 
-            if let Some(ref mut left_children) = left_sibling.children {
-                let mut child = left_children.pop().unwrap();
-                child.parent = Some((ptr::from_mut(self), 0));
-
-                self.children.as_mut().unwrap().iter_mut().for_each(|item| item.parent.as_mut().unwrap().1 += 1);
-                self.children.as_mut().unwrap().insert(0,child);
+                // 子树中的键都小于 keys[index], 如果子树里找不到, 就会沿着 parent 回到 (node, index)
+                if (*node).height > 0 { node = Self::children(node)[index] }
+                else if index < keys.len() { return Some((node, index)) }
+                else { return Self::get_next(node, index, true) }
             }
         }
     }
 
-    /// 合并同级两个兄弟节点, 把当前节点的下一个节点合并到当前节点
-    fn merge(current_node: &mut Self)
+    unsafe fn bound_position(this: *mut Self, bound: Bound<&K>, unbounded: Option<(*mut Self, usize)>) -> Option<(*mut Self, usize)>
     {
-        let (parent, parent_idx)  = unsafe { 
-            let (a,b) = current_node.parent.expect("必须要有父节点");
-            (a.as_mut().unwrap(), b)
-        };
-
-        let mid_member = parent.members.remove(parent_idx);
-        let mut right_node = parent.children.as_mut().unwrap().remove(parent_idx + 1);
-
-        // 拿出右节点后, 修正后续节点在父节点中的位置
-        parent.children.as_mut().unwrap()[parent_idx + 1 ..].iter_mut().for_each(|item| item.parent.as_mut().unwrap().1 -= 1);
-
-        current_node.members.push(mid_member);
-        current_node.members.append(&mut right_node.members);
-
-        let current_ptr = ptr::from_mut(current_node);
-        if let Some(ref mut children) = current_node.children
+        match bound
         {
-            children.append(right_node.children.as_mut().unwrap());
-            
-            children[0 ..].iter_mut().enumerate().for_each(|(i,child)| child.parent = Some((current_ptr, i)));
+            Bound::Unbounded => unbounded,
+            Bound::Included(key) => unsafe { Self::lower_bound(this, key, true) },
+            Bound::Excluded(key) => unsafe { Self::lower_bound(this, key, false) }
         }
     }
 }
 
-#[cfg(test)]
-mod tests
+/// 按键的顺序遍历某个区间的迭代器, end 是区间之后的第一个成员位置
+pub struct Range<'a, K: Ord, V>
 {
-use super::*;
+    current: Option<(*mut Node<K,V>, usize)>,
+    end: Option<(*mut Node<K,V>, usize)>,
+    _marker: PhantomData<&'a Node<K,V>>
+}
 
-#[test]
-fn get_from_sibling_works_l()
+impl<'a, K:Ord, V> Iterator for Range<'a, K,V>
 {
-    let mut btr = Btree::new();
-    [(1, 8), (4, 9), (6, 2), (8, 10), (11, 11), (13, 3)].into_iter().for_each(|(k,v)| { btr.insert(k, v); });
-    let children = unsafe { (*btr.root).children.as_mut().unwrap() };
-    children[0].get_from_sibling(true);
-    unsafe {
-        assert_eq!(&(*btr.root).members[0], &(8,10));
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        let (node, idx) = self.current?;
+        if self.current == self.end {
+            self.current = None;
+            return None
+        }
+        self.current = unsafe { Node::get_next(node, idx, false) };
+        Some( unsafe { (&Node::keys(node)[idx], &Node::vals(node)[idx]) } )
     }
-    children[0].members.iter().for_each(|item| println!("一: {:?}", item));
-    children[1].members.iter().for_each(|item| println!("二: {:?}", item));
 }
 
-#[test]
-fn get_from_sibling_r()
+impl<K:Ord, V, A: Allocator> Btree<K,V,A> {
+    /// 按键的顺序遍历落在 range 内的成员. 起点大于终点时返回空迭代器.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Range<'_, K,V>
+    {
+        unsafe {
+            let leaf = Node::first_leaf(self.root);
+            let first = ((*leaf).len > 0).then_some((leaf, 0));
+            let current = Node::bound_position(self.root, range.start_bound(), first);
+            // 区间的终点转换成区间之后的第一个位置: 包含 key 时是第一个大于 key 的成员, 不包含时是第一个大于等于 key 的成员
+            let end = match range.end_bound()
+            {
+                Bound::Unbounded => None,
+                Bound::Included(key) => Node::bound_position(self.root, Bound::Excluded(key), None),
+                Bound::Excluded(key) => Node::bound_position(self.root, Bound::Included(key), None)
+            };
+
+            let current = match (current, end)
+            {
+                (Some((c, ci)), Some((e, ei))) if Node::keys(c)[ci].cmp(&Node::keys(e)[ei]).is_ge() => None,
+                _ => current
+            };
+            Range{ current, end, _marker: PhantomData }
+        }
+    }
+}
+
+
+impl<K: Ord, V> Node<K,V>
 {
-    let mut btr = Btree::new();
-    [(1, 8), (4, 9), (6, 2), (8, 10), (11, 11), (13, 3)].into_iter().for_each(|(k,v)| { btr.insert(k, v); });
-    let children = unsafe { (*btr.root).children.as_mut().unwrap() };
-    children[1].get_from_sibling(false);
-    unsafe {
-        assert_eq!(&(*btr.root).members[0], &(4,9));
+    /// 从兄弟节点移动成员到本节点, origin 是 true 表示右边节点减少成员, origin 是 false 表示左边节点减少成员. 本函数不检查左边或者右边是否有兄弟节点.
+    /// parent 和 parent_idx 是本节点的父节点和本节点在其中的位置.
+    unsafe fn get_from_sibling(this: *mut Self, parent: *mut Self, parent_idx: usize, origin: bool)
+    {
+        unsafe {
+            if origin {
+                let right_sibling = Self::children(parent)[parent_idx + 1];
+                let (key, value) = Self::remove_member(right_sibling, 0); //提取右兄弟的第一个成员
+                let (key, value) = Self::replace_member(parent, parent_idx, key, value);
+                let len = (*this).len;
+                Self::key_ptr(this).add(len).write(key);
+                Self::val_ptr(this).add(len).write(value);
+                (*this).len += 1;
+
+                if (*this).height > 0 {
+                    let child = array_remove(Self::child_ptr(right_sibling), (*right_sibling).len + 2, 0);
+                    *Self::child_ptr(this).add(len + 1) = child;
+                    Self::fix_children_parent(right_sibling, 0);
+                    Self::fix_children_parent(this, len + 1);
+                }
+            }
+            else {
+                let left_sibling = Self::children(parent)[parent_idx - 1];
+                let left_len = (*left_sibling).len - 1;
+                let key = Self::key_ptr(left_sibling).add(left_len).read();
+                let value = Self::val_ptr(left_sibling).add(left_len).read();
+                (*left_sibling).len = left_len;
+                let (key, value) = Self::replace_member(parent, parent_idx - 1, key, value);
+
+                if (*this).height > 0 {
+                    let child = *Self::child_ptr(left_sibling).add(left_len + 1);
+                    array_insert(Self::child_ptr(this), (*this).len + 1, 0, child);
+                }
+                Self::insert_member(this, 0, key, value);
+                if (*this).height > 0 {
+                    Self::fix_children_parent(this, 0);
+                }
+            }
+        }
+    }
+
+    /// 合并同级两个兄弟节点, 把当前节点的下一个节点合并到当前节点. parent 和 parent_idx 是当前节点的父节点和它在其中的位置.
+    unsafe fn merge<A: Allocator>(current_node: *mut Self, parent: *mut Self, parent_idx: usize, alloc: &A)
+    {
+        unsafe {
+
+            let right_node = Self::children(parent)[parent_idx + 1];
+            let mid_member = Self::remove_member(parent, parent_idx);
+            array_remove(Self::child_ptr(parent), (*parent).len + 2, parent_idx + 1);
+
+            // 拿出右节点后, 修正后续节点在父节点中的位置
+            Self::fix_children_parent(parent, parent_idx + 1);
+
+            let len = (*current_node).len;
+            let right_len = (*right_node).len;
+            Self::key_ptr(current_node).add(len).write(mid_member.0);
+            Self::val_ptr(current_node).add(len).write(mid_member.1);
+            ptr::copy_nonoverlapping(Self::key_ptr(right_node), Self::key_ptr(current_node).add(len + 1), right_len);
+            ptr::copy_nonoverlapping(Self::val_ptr(right_node), Self::val_ptr(current_node).add(len + 1), right_len);
+            (*current_node).len = len + 1 + right_len;
+
+            if (*current_node).height > 0 {
+                ptr::copy_nonoverlapping(Self::child_ptr(right_node), Self::child_ptr(current_node).add(len + 1), right_len + 1);
+                Self::fix_children_parent(current_node, len + 1);
+            }
+            Self::dealloc(right_node, alloc);
+        }
     }
-    children[0].members.iter().for_each(|item| println!("一: {:?}", item));
-    children[1].members.iter().for_each(|item| println!("二: {:?}", item));
 }
+
+/// Btree 插入时的节点操作, 分裂需要的新节点由 new_node 根据高度提供. 被修改的节点记录在 touched 中.
+struct Splitter<'a, K:Ord, V, F: FnMut(usize) -> *mut Node<K,V>>
+{
+    counters: &'a mut OpCounters,
+    touched: &'a mut Touched<K,V>,
+    new_node: &'a mut F
 }
 
-impl<K:Ord, V> Node<K,V>
+impl<K:Ord, V, F: FnMut(usize) -> *mut Node<K,V>> NodeAccess for Splitter<'_, K,V,F>
 {
-    fn remove(this: &mut Self, index: usize) -> (Option<*mut Self>, (K,V))
+    type Node = *mut Node<K,V>;
+    type Error = Infallible;
+
+    fn len(&mut self, node: Self::Node) -> Result<usize, Infallible>
     {
-        let (mut current_node, deleted_element) = match this.children.as_mut() {
-            None => {
-                let element_in_leaf = this.members.remove(index);
-                (this, element_in_leaf)
-            },
-            Some(_) => {
-                let (ptr, idx) = unsafe { Self::get_next(ptr::from_mut(this), index, false).unwrap() };
-                let reference = unsafe { ptr.as_mut().unwrap() };
-                let element_in_leaf = reference.members.remove(idx);
-                (reference, replace(&mut this.members[index], element_in_leaf))
-            }
-        };
+        Ok(unsafe { (*node).len })
+    }
 
-        let root_node = loop {
-            if current_node.members.len() + 1 >= (RANK + 1) / 2 { break None }
+    fn child(&mut self, parent: Self::Node, idx: usize) -> Result<Self::Node, Infallible>
+    {
+        Ok(unsafe { Node::children(parent)[idx] })
+    }
 
-            let (parent, parent_idx) = match current_node.parent {
-                None => break Some(current_node),
-                //Some((parent_ptr,_)) if unsafe { (*parent_ptr).members.is_empty() } => break Some(current_node),
-                Some((parent_ptr, parent_idx)) => (unsafe { parent_ptr.as_mut().unwrap() }, parent_idx)
-            };
+    fn changed(&mut self, node: Self::Node) -> Result<(), Infallible>
+    {
+        self.touched.push(node);
+        Ok(())
+    }
+}
+
+impl<K:Ord, V, F: FnMut(usize) -> *mut Node<K,V>> Split for Splitter<'_, K,V,F>
+{
+    type Member = (K,V);
+
+    fn split_off(&mut self, node: Self::Node, mid: usize) -> Result<((K,V), Self::Node), Infallible>
+    {
+        unsafe {
+            let right_len = (*node).len - mid - 1;
+            let right = (self.new_node)((*node).height);
 
-            let sibling = parent.children.as_mut().unwrap();
-            if parent_idx + 1 < sibling.len() && sibling[parent_idx + 1].members.len() + 1 > (RANK + 1) / 2 {
-                current_node.get_from_sibling(true);
-                break None;
+            ptr::copy_nonoverlapping(Node::key_ptr(node).add(mid + 1), Node::key_ptr(right), right_len);
+            ptr::copy_nonoverlapping(Node::val_ptr(node).add(mid + 1), Node::val_ptr(right), right_len);
+            let member = (Node::key_ptr(node).add(mid).read(), Node::val_ptr(node).add(mid).read());
+            (*node).len = mid;
+            (*right).len = right_len;
+
+            if (*node).height > 0 {
+                ptr::copy_nonoverlapping(Node::child_ptr(node).add(mid + 1), Node::child_ptr(right), right_len + 1);
+                Node::fix_children_parent(right, 0);
             }
-            else if parent_idx > 0 && sibling[parent_idx - 1].members.len() + 1 > (RANK + 1) / 2 {
-                current_node.get_from_sibling(false);
-                break None;
+            self.counters.splits += 1;
+            Ok((member, right))
+        }
+    }
+
+    fn insert_child(&mut self, parent: Self::Node, idx: usize, (key, value): (K,V), right: Self::Node) -> Result<(), Infallible>
+    {
+        unsafe {
+            Node::insert_member(parent, idx, key, value);
+            array_insert(Node::child_ptr(parent), (*parent).len, idx + 1, right);
+            Node::fix_children_parent(parent, idx + 1);
+        }
+        Ok(())
+    }
+
+    fn new_root(&mut self, left: Self::Node, (key, value): (K,V), right: Self::Node) -> Result<Self::Node, Infallible>
+    {
+        unsafe {
+            let root = (self.new_node)((*left).height + 1);
+            Node::insert_member(root, 0, key, value);
+            *Node::child_ptr(root) = left;
+            *Node::child_ptr(root).add(1) = right;
+            Node::fix_children_parent(root, 0);
+            Ok(root)
+        }
+    }
+}
+
+/// Btree 的节点操作, 修改兄弟节点之前先用 cloner 复制被快照共享的节点. 只在 Node::remove 中构造,
+/// 交给它的节点都是有效的, 并且从根节点到它们的路径上没有共享的节点. 被修改的节点记录在 touched 中.
+struct Rebalancer<'a, K:Ord, V, A: Allocator>
+{
+    counters: &'a mut OpCounters,
+    cloner: Option<Cloner<K,V,A>>,
+    touched: &'a mut Touched<K,V>,
+    alloc: &'a A
+}
+
+impl<K:Ord, V, A: Allocator> NodeAccess for Rebalancer<'_, K,V,A>
+{
+    type Node = *mut Node<K,V>;
+    type Error = Infallible;
+
+    fn len(&mut self, node: Self::Node) -> Result<usize, Infallible>
+    {
+        Ok(unsafe { (*node).len })
+    }
+
+    fn child(&mut self, parent: Self::Node, idx: usize) -> Result<Self::Node, Infallible>
+    {
+        Ok(unsafe { Node::children(parent)[idx] })
+    }
+
+    fn changed(&mut self, node: Self::Node) -> Result<(), Infallible>
+    {
+        self.touched.push(node);
+        Ok(())
+    }
+}
+
+impl<K:Ord, V, A: Allocator> Rebalance for Rebalancer<'_, K,V,A>
+{
+    fn borrow_from_right(&mut self, parent: Self::Node, idx: usize) -> Result<(), Infallible>
+    {
+        unsafe {
+            Node::unique_child(parent, idx + 1, self.cloner, self.alloc);
+            Node::get_from_sibling(Node::children(parent)[idx], parent, idx, true);
+        }
+        self.counters.borrows += 1;
+        Ok(())
+    }
+
+    fn borrow_from_left(&mut self, parent: Self::Node, idx: usize) -> Result<(), Infallible>
+    {
+        unsafe {
+            Node::unique_child(parent, idx - 1, self.cloner, self.alloc);
+            Node::get_from_sibling(Node::children(parent)[idx], parent, idx, false);
+        }
+        self.counters.borrows += 1;
+        Ok(())
+    }
+
+    fn merge(&mut self, parent: Self::Node, idx: usize) -> Result<(), Infallible>
+    {
+        // 合并会修改左节点并释放右节点, 两个节点都不能是共享的
+        unsafe {
+            let left = Node::unique_child(parent, idx, self.cloner, self.alloc);
+            Node::unique_child(parent, idx + 1, self.cloner, self.alloc);
+            Node::merge(left, parent, idx, self.alloc);
+        }
+        self.counters.merges += 1;
+        Ok(())
+    }
+}
+
+impl<K:Ord, V> Node<K,V>
+{
+    /// 删除 this 的第 index 个成员. path 是从根节点到 this 的父节点的路径, 调用者保证路径上没有共享的节点,
+    /// 本函数会在修改其他节点之前用 cloner 复制它们. 被修改的节点自底向上记录在 touched 中, 不包括被换掉的根节点.
+    unsafe fn remove<A: Allocator>(this: *mut Self, index: usize, path: &mut Path<K,V>, counters: &mut OpCounters,
+                                   cloner: Option<Cloner<K,V,A>>, touched: &mut Touched<K,V>, alloc: &A)
+        -> (Option<*mut Self>, (K,V))
+    {
+        unsafe {
+            let (current_node, deleted_element) = if (*this).height == 0 {
+                (this, Self::remove_member(this, index))
             }
             else {
-                if parent_idx + 1 < sibling.len() {
-                    Self::merge(current_node);
-                    current_node = parent;
-                }
-                else {
-                    current_node = sibling[parent_idx - 1].as_mut();
-                    Self::merge(current_node);
-                    current_node = parent;
+                // 后继成员在右子树最左边的叶子里
+                path.push((this, index + 1));
+                let mut ptr = Self::unique_child(this, index + 1, cloner, alloc);
+                while (*ptr).height > 0 {
+                    path.push((ptr, 0));
+                    ptr = Self::unique_child(ptr, 0, cloner, alloc);
                 }
-            }
-        };
+                let (key, value) = Self::remove_member(ptr, 0);
+                (ptr, Self::replace_member(this, index, key, value))
+            };
+
+            let mut nodes = Rebalancer{ counters, cloner, touched, alloc };
+            let Ok(root_node) = rebalance(&mut nodes, current_node, path);
 
-        match root_node {
-            None => (None, deleted_element),
-            Some(root_node) if !root_node.members.is_empty() => (None, deleted_element),
-            Some(root_node) => match root_node.children {
-                None => (None, deleted_element),
-                Some(ref mut children) => (Some(box_as_mut_ptr(&mut children[0])), deleted_element)
+            match root_node {
+                Some(root_node) if (*root_node).len == 0 && (*root_node).height > 0 => {
+                    // 根节点最后交给 changed, 它将被释放
+                    debug_assert_eq!(touched.last(), Some(&root_node));
+                    touched.pop();
+                    (Some(*Self::child_ptr(root_node)), deleted_element)
+                }
+                _ => (None, deleted_element)
             }
         }
     }
 }
 
-impl<K:Ord, V> Btree<K,V>
+impl<K:Ord, V, A: Allocator> Btree<K,V,A>
 {
     pub fn remove(&mut self, key: &K) -> Option<(K,V)>
     {
-        match Node::search(unsafe { self.root.as_mut().unwrap() }, key)
+        // 有快照时先确认键存在, 避免为不存在的键复制路径
+        if self.cloner.get().is_some() { self.get(key)?; }
+        let mut path = Path::new();
+        match unsafe { self.search_mut(key, &mut path) }
         {
             SearchResult::NonFound(_, _ ) => None,
-            SearchResult::Found(ptr, index) => {
-                let target = unsafe { ptr.as_mut().unwrap() };
-                let (root,deleted_element) = Node::remove(target, index);
-                match root {
-                    None => Some(deleted_element),
-                    Some(new_root) => {
-                        unsafe { 
-                            Box::leak((*self.root).children.as_mut().unwrap().pop().unwrap());
-                            self.root.as_mut().unwrap().children = None; 
-                            drop(Box::from_raw(self.root));
-                            (*new_root).parent = None;
-                            self.root = new_root;
-                        }
-                        Some(deleted_element)
-                    }
-                }
+            SearchResult::Found(ptr, index) => Some(unsafe { self.remove_found(ptr, index, path) })
+        }
+    }
+
+    /// 和 remove 一样, 但是复制被快照共享的节点时内存分配失败返回 AllocError, 树的内容保持不变.
+    /// 删除可能修改的共享节点在修改树之前全部复制好.
+    pub fn try_remove(&mut self, key: &K) -> Result<Option<(K,V)>, AllocError>
+    {
+        if self.cloner.get().is_some() && self.get(key).is_none() { return Ok(None) }
+        let mut path = Path::new();
+        match unsafe { self.try_search_mut(key, &mut path)? }
+        {
+            SearchResult::NonFound(_, _ ) => Ok(None),
+            SearchResult::Found(ptr, index) => unsafe {
+                Node::try_unique_for_remove(ptr, index, &path, self.cloner.get(), &self.alloc)?;
+                Ok(Some(self.remove_found(ptr, index, path)))
             }
         }
     }
+
+    /// 删除 search_mut 找到的成员. path 上的节点都不是共享的
+    unsafe fn remove_found(&mut self, ptr: *mut Node<K,V>, index: usize, mut path: Path<K,V>) -> (K,V)
+    {
+        let mut touched = Touched::new();
+        let (root,deleted_element) = unsafe { Node::remove(ptr, index, &mut path, &mut self.counters, self.cloner.get(), &mut touched, &self.alloc) };
+        if let Some(new_root) = root {
+            unsafe {
+                Node::dealloc(self.root, &self.alloc);
+                (*new_root).parent = None;
+            }
+            self.root = new_root;
+        }
+        self.augment_touched(&touched);
+        deleted_element
+    }
 }
 
 pub struct IterMut<'a, K: Ord, V>
@@ -440,70 +1251,403 @@ pub struct IterMut<'a, K: Ord, V>
     current_node_ptr: NonNull<Node<K,V>>,
     idx: usize,
     is_first: bool,
-    _marker: PhantomData<&'a mut (K,V)>
+    _marker: PhantomData<&'a mut Node<K,V>>
 }
 
 impl<'a, K:Ord, V> Iterator for IterMut<'a, K,V>
 {
-    type Item = &'a mut (K,V);
+    type Item = (&'a K, &'a mut V);
 
     fn next(&mut self) -> Option<Self::Item>
     {
+        let node = self.current_node_ptr.as_ptr();
         if self.is_first {
             self.is_first = false;
-            if unsafe { self.current_node_ptr.as_ref().members.is_empty() } { None }
-            else { Some( unsafe { &mut self.current_node_ptr.as_mut().members[self.idx] } ) }
+            if unsafe { (*node).len } == 0 { None }
+            else { Some( unsafe { (&Node::keys(node)[self.idx], &mut *Node::val_ptr(node).add(self.idx)) } ) }
         }
         else {
-            match unsafe { Node::get_next(self.current_node_ptr.as_ptr(), self.idx, false) }
+            match unsafe { Node::get_next(node, self.idx, false) }
             {
                 None => None,
                 Some((pt,i)) => {
                     self.current_node_ptr = NonNull::new(pt).unwrap();
                     self.idx = i;
-                    Some( unsafe { &mut self.current_node_ptr.as_mut().members[i] } )
+                    Some( unsafe { (&Node::keys(pt)[i], &mut *Node::val_ptr(pt).add(i)) } )
                 }
             }
         }
     }
 }
 
-impl<'a, K:Ord, V> Btree<K,V> {
-    pub fn iter_mut(&mut self) -> IterMut<'a, K,V>
+impl<K:Ord, V, A: Allocator> Btree<K,V,A> {
+    pub fn iter_mut(&mut self) -> IterMut<'_, K,V>
     {
-        match unsafe{ &mut (*self.root).children }
-        {
-            None => IterMut{ current_node_ptr: NonNull::new(self.root).unwrap(), idx:0,  is_first: true, _marker:PhantomData },
-            Some(children) => {
-                let mut ptr = &mut children[0];
-                while let Some(ref mut child) = (*ptr).children {
-                    ptr = &mut child[0];
-                }
+        if self.cloner.get().is_some() {
+            // 所有的值都可能被修改, 先复制所有被快照共享的节点
+            unsafe { self.unique_root(); Node::unique_subtree(self.root, self.cloner.get(), &self.alloc) };
+        }
+        let leaf = unsafe { Node::first_leaf(self.root) };
+        IterMut{ current_node_ptr: NonNull::new(leaf).unwrap(), idx: 0, is_first: true, _marker: PhantomData }
+    }
+}
 
-                IterMut{ current_node_ptr: NonNull::new(box_as_mut_ptr(ptr)).unwrap() , idx: 0, is_first: true, _marker:PhantomData }
+impl<K:Ord, V, A: Allocator> Btree<K,V,A>
+{
+    /// 和 get 一样, 找不到键时返回 Error::KeyNotFound
+    pub fn try_get(&self, key: &K) -> Result<&V, Error>
+    {
+        self.get(key).ok_or(Error::KeyNotFound)
+    }
+
+    /// 和 get_mut 一样, 找不到键时返回 Error::KeyNotFound, 复制被快照共享的节点时内存分配失败返回 Error::Alloc
+    pub fn try_get_mut(&mut self, key: &K) -> Result<&mut V, Error>
+    {
+        unsafe {
+            match self.try_search_mut(key, &mut Path::new())?
+            {
+                SearchResult::Found(p, idx) => Ok(&mut *Node::val_ptr(p).add(idx)),
+                SearchResult::NonFound(_, _) => Err(Error::KeyNotFound)
             }
         }
     }
+
+    /// 不会 panic 的 `tree[key]`
+    pub fn try_index(&self, index: K) -> Result<&V, Error>
+    {
+        self.try_get(&index)
+    }
+
+    pub fn try_index_mut(&mut self, index: K) -> Result<&mut V, Error>
+    {
+        self.try_get_mut(&index)
+    }
 }
 
-impl<K:Ord, V> Index<K> for Btree<K,V>
+impl<K:Ord + Debug, V, A: Allocator> Btree<K,V,A>
+{
+    /// 和 `tree[key]` 一样找不到键时 panic, 但是 panic 消息中带上键的 Debug 输出
+    #[track_caller]
+    pub fn expect(&self, key: &K) -> &V
+    {
+        self.get(key).unwrap_or_else(|| key_not_found_debug(key))
+    }
+
+    #[track_caller]
+    pub fn expect_mut(&mut self, key: &K) -> &mut V
+    {
+        self.get_mut(key).unwrap_or_else(|| key_not_found_debug(key))
+    }
+}
+
+impl<K:Ord, V, A: Allocator> Index<K> for Btree<K,V,A>
 {
     type Output = V;
 
     fn index(&self, index: K) -> &Self::Output {
         match self.get(&index) {
-            None => panic!("集合内没有这个键!"),
+            None => key_not_found(),
             Some(val) => val
         }
     }
 }
 
-impl<K:Ord, V> IndexMut<K> for Btree<K,V>
+impl<K:Ord, V, A: Allocator> IndexMut<K> for Btree<K,V,A>
 {
     fn index_mut(&mut self, index: K) -> &mut Self::Output {
         match self.get_mut(&index) {
-            None => panic!("集合内没有这个键!"),
+            None => key_not_found(),
             Some(val) => val
         }
     }
-}
\ No newline at end of file
+}
+
+impl<K:Ord + Clone, V: Clone, A: Allocator + Clone> Btree<K,V,A>
+{
+    /// O(1) 地创建当前内容的只读快照. 快照和树共享所有节点, 之后树在修改节点前才复制被共享的节点,
+    /// 每次修改只复制一条路径. 快照不受之后的修改影响, 在 K 和 V 满足 Send + Sync 时可以交给其他线程读取.
+    pub fn snapshot(&self) -> BtreeSnapshot<K,V,A>
+    {
+        self.cloner.set(Some(Node::clone_node));
+        unsafe {
+            (*self.root).refs.fetch_add(1, Ordering::Relaxed);
+            BtreeSnapshot{ root: NonNull::new_unchecked(self.root), alloc: self.alloc.clone(), _marker: PhantomData }
+        }
+    }
+}
+
+/// [`Btree::snapshot`] 创建的只读快照, 克隆也是 O(1) 的.
+/// 快照共享的节点的 parent 指针可能已经被树改写, 所以快照只从根节点向下访问节点.
+/// 快照持有分配器的一份克隆, 最后一个引用节点的树或者快照负责释放节点.
+pub struct BtreeSnapshot<K:Ord, V, A: Allocator = Global>
+{
+    root: NonNull<Node<K,V>>,
+    alloc: A,
+    _marker: PhantomData<Box<Node<K,V>>>
+}
+
+unsafe impl<K:Ord + Send + Sync, V: Send + Sync, A: Allocator + Send + Sync> Send for BtreeSnapshot<K,V,A> {}
+unsafe impl<K:Ord + Send + Sync, V: Send + Sync, A: Allocator + Send + Sync> Sync for BtreeSnapshot<K,V,A> {}
+
+impl<K:Ord, V, A: Allocator> BtreeSnapshot<K,V,A>
+{
+    pub fn get(&self, key: &K) -> Option<&V>
+    {
+        unsafe{
+            match Node::search(self.root.as_ptr(), key)
+            {
+                SearchResult::Found(p, idx) => Some(&*Node::val_ptr(p).add(idx)),
+                SearchResult::NonFound(_, _) => None
+            }
+        }
+    }
+
+    pub fn iter(&self) -> SnapshotIter<'_, K,V>
+    {
+        let mut iter = SnapshotIter{ stack: Vec::new(), _marker: PhantomData };
+        unsafe { iter.push_left_edge(self.root.as_ptr()) };
+        iter
+    }
+}
+
+impl<K:Ord, V, A: Allocator + Clone> Clone for BtreeSnapshot<K,V,A>
+{
+    fn clone(&self) -> Self {
+        unsafe { self.root.as_ref().refs.fetch_add(1, Ordering::Relaxed) };
+        Self{ root: self.root, alloc: self.alloc.clone(), _marker: PhantomData }
+    }
+}
+
+impl<K:Ord, V, A: Allocator> Drop for BtreeSnapshot<K,V,A>
+{
+    fn drop(&mut self) {
+        unsafe { Node::release(self.root.as_ptr(), &self.alloc) };
+    }
+}
+
+impl<K:Ord, V, A: Allocator> Index<K> for BtreeSnapshot<K,V,A>
+{
+    type Output = V;
+
+    fn index(&self, index: K) -> &Self::Output {
+        match self.get(&index) {
+            None => key_not_found(),
+            Some(val) => val
+        }
+    }
+}
+
+/// 快照的中序遍历迭代器. 和 PersistentBtree 的迭代器一样用栈代替 parent 指针,
+/// 栈里保存节点和下一个要返回的成员下标
+pub struct SnapshotIter<'a, K: Ord, V>
+{
+    stack: Vec<(*mut Node<K,V>, usize)>,
+    _marker: PhantomData<&'a Node<K,V>>
+}
+
+impl<K:Ord, V> SnapshotIter<'_, K,V>
+{
+    unsafe fn push_left_edge(&mut self, mut node: *mut Node<K,V>)
+    {
+        unsafe {
+            loop {
+                self.stack.push((node, 0));
+                if (*node).height == 0 { return }
+                node = Node::children(node)[0];
+            }
+        }
+    }
+}
+
+impl<'a, K:Ord, V> Iterator for SnapshotIter<'a, K,V>
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        unsafe {
+            loop {
+                let (node, idx) = *self.stack.last()?;
+                if idx < (*node).len {
+                    self.stack.last_mut().unwrap().1 += 1;
+                    if (*node).height > 0 { self.push_left_edge(Node::children(node)[idx + 1]); }
+                    return Some((&*Node::key_ptr(node).add(idx), &*Node::val_ptr(node).add(idx)));
+                }
+                self.stack.pop();
+            }
+        }
+    }
+}
+
+/// 树的形状和空间占用统计, 由 [`Btree::stats`] 生成
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TreeStats
+{
+    /// 树的层数, 只有根节点时为 1
+    pub height: usize,
+    /// 每一层的节点数, 下标 0 是根节点所在的层
+    pub nodes_per_level: Vec<usize>,
+    pub leaf_nodes: usize,
+    pub internal_nodes: usize,
+    /// 所有节点的成员总数
+    pub entries: usize,
+    /// 单个节点最多能容纳的成员数
+    pub node_capacity: usize,
+    /// 平均每个节点的填充率, 即 entries / (节点数 * node_capacity)
+    pub average_occupancy: f64,
+    pub splits: u64,
+    pub merges: u64,
+    pub borrows: u64,
+    /// 所有叶子节点在堆上占用的字节数
+    pub leaf_bytes: usize,
+    /// 所有内部节点在堆上占用的字节数
+    pub internal_bytes: usize
+}
+
+impl<K:Ord, V> Node<K,V>
+{
+    unsafe fn collect_stats(this: *mut Self, depth: usize, stats: &mut TreeStats)
+    {
+        unsafe {
+            if stats.nodes_per_level.len() <= depth { stats.nodes_per_level.push(0); }
+            stats.nodes_per_level[depth] += 1;
+            stats.entries += (*this).len;
+
+            if (*this).height == 0 {
+                stats.leaf_nodes += 1;
+                stats.leaf_bytes += size_of::<Self>();
+            }
+            else {
+                stats.internal_nodes += 1;
+                stats.internal_bytes += size_of::<InternalNode<K,V>>();
+                Self::children(this).iter().for_each(|&child| Self::collect_stats(child, depth + 1, stats));
+            }
+        }
+    }
+}
+
+impl<K:Ord, V, A: Allocator> Btree<K,V,A>
+{
+    /// 遍历整棵树, 统计各层节点数、填充率和内存占用, 以及创建以来的分裂、合并、借位次数.
+    pub fn stats(&self) -> TreeStats
+    {
+        let mut stats = TreeStats{
+            node_capacity: RANK - 1,
+            splits: self.counters.splits,
+            merges: self.counters.merges,
+            borrows: self.counters.borrows,
+            ..TreeStats::default()
+        };
+        unsafe { Node::collect_stats(self.root, 0, &mut stats) };
+
+        stats.height = stats.nodes_per_level.len();
+        let nodes = stats.leaf_nodes + stats.internal_nodes;
+        stats.average_occupancy = stats.entries as f64 / (nodes * stats.node_capacity) as f64;
+        stats
+    }
+}
+
+/// 把 Debug 输出转义成 Graphviz record 标签里可以直接使用的文本
+fn escape_dot_label(text: &str) -> String
+{
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '{' | '}' | '|' | '<' | '>' | '"' | '\\') { escaped.push('\\'); }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl<K:Ord + Debug, V: Debug> Node<K,V>
+{
+    /// 把以 this 为根的子树写成 DOT 语句, id 是下一个可用的节点编号, 返回本节点的编号
+    unsafe fn write_dot(this: *mut Self, out: &mut String, id: &mut usize) -> usize
+    {
+        let my_id = *id;
+        *id += 1;
+
+        let (keys, vals) = unsafe { (Self::keys(this), Self::vals(this)) };
+        let mut label = String::new();
+        for (i, (k, v)) in keys.iter().zip(vals).enumerate() {
+            let _ = write!(label, "<f{}> |{}|", i, escape_dot_label(&format!("{:?}: {:?}", k, v)));
+        }
+        let _ = write!(label, "<f{}> ", keys.len());
+        let _ = writeln!(out, "    n{} [label=\"{}\"];", my_id, label);
+
+        for (i, &child) in unsafe { Self::children(this) }.iter().enumerate() {
+            let child_id = unsafe { Self::write_dot(child, out, id) };
+            let _ = writeln!(out, "    n{}:f{} -> n{};", my_id, i, child_id);
+        }
+        my_id
+    }
+}
+
+impl<K:Ord + Debug, V: Debug, A: Allocator> Btree<K,V,A>
+{
+    /// 生成整棵树的 Graphviz 描述, 每个节点画成一个 record, 成员之间的格子连向对应的子节点.
+    pub fn to_dot(&self) -> String
+    {
+        let mut out = String::from("digraph Btree {\n    node [shape=record];\n");
+        unsafe { Node::write_dot(self.root, &mut out, &mut 0) };
+        out.push_str("}\n");
+        out
+    }
+
+    /// 逐层打印树的结构, 每行是一层, 同一层的节点从左到右排列.
+    pub fn dump_ascii(&self) -> String
+    {
+        let mut out = String::new();
+        let mut level = vec![self.root];
+        let mut depth = 0;
+
+        while !level.is_empty() {
+            let _ = write!(out, "L{}:", depth);
+            let mut next_level = Vec::new();
+            for node in level {
+                out.push_str(" [");
+                let (keys, vals) = unsafe { (Node::keys(node), Node::vals(node)) };
+                for (i, (k, v)) in keys.iter().zip(vals).enumerate() {
+                    if i > 0 { out.push_str(", "); }
+                    let _ = write!(out, "{:?}: {:?}", k, v);
+                }
+                out.push(']');
+                next_level.extend_from_slice(unsafe { Node::children(node) });
+            }
+            out.push('\n');
+            level = next_level;
+            depth += 1;
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+use super::*;
+use std::println;
+
+#[test]
+fn get_from_sibling_works_l()
+{
+    let mut btr = Btree::new();
+    [(1, 8), (4, 9), (6, 2), (8, 10), (11, 11), (13, 3)].into_iter().for_each(|(k,v)| { btr.insert(k, v); });
+    unsafe {
+        Node::get_from_sibling(Node::children(btr.root)[0], btr.root, 0, true);
+        assert_eq!((Node::keys(btr.root)[0], Node::vals(btr.root)[0]), (8,10));
+    }
+    println!("{}", btr.dump_ascii());
+}
+
+#[test]
+fn get_from_sibling_r()
+{
+    let mut btr = Btree::new();
+    [(1, 8), (4, 9), (6, 2), (8, 10), (11, 11), (13, 3)].into_iter().for_each(|(k,v)| { btr.insert(k, v); });
+    unsafe {
+        Node::get_from_sibling(Node::children(btr.root)[1], btr.root, 1, false);
+        assert_eq!((Node::keys(btr.root)[0], Node::vals(btr.root)[0]), (4,9));
+    }
+    println!("{}", btr.dump_ascii());
+}
+}
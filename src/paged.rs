@@ -0,0 +1,797 @@
+use std::borrow::ToOwned;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::mem::replace;
+use std::path::Path;
+use std::vec;
+use std::vec::Vec;
+
+use crate::codec::{take_bytes, Codec};
+use crate::inline_vec::InlineVec;
+use crate::rebalance::{rebalance, split, NodeAccess, Rebalance, Split};
+use crate::search::search_keys;
+use crate::wal::{Operation, Wal};
+use crate::RANK;
+
+/// 每个节点占用一页
+pub const PAGE_SIZE: usize = 4096;
+/// 缓冲池默认缓存的节点数
+pub const DEFAULT_FRAMES: usize = 64;
+
+const MAGIC: &[u8; 8] = b"NBTPAGE1";
+/// 节点页的开头: u16 成员数, u8 是否为叶子
+const NODE_HEADER: usize = 3;
+/// 单个键值对编码后的最大字节数, 保证写回磁盘的节点 (最多 RANK - 1 个成员) 一定能放进一页
+pub const MAX_ENTRY_SIZE: usize = (PAGE_SIZE - NODE_HEADER - 4 * RANK) / (RANK - 1);
+
+/// 日志超过这个大小时在操作提交之后自动做检查点
+const CHECKPOINT_WAL_SIZE: u64 = 4 << 20;
+
+/// 页号. 0 号页是文件头, 所以 0 也表示空闲链表的结尾和还没有创建的根节点
+pub(crate) type PageId = u32;
+
+fn invalid_data(msg: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// PagedBtree 的数据文件和日志文件需要的操作
+pub trait Storage: Read + Write + Seek
+{
+    /// 把之前写入的内容持久化
+    fn sync(&mut self) -> io::Result<()>;
+
+    fn set_len(&mut self, len: u64) -> io::Result<()>;
+}
+
+impl Storage for File
+{
+    fn sync(&mut self) -> io::Result<()>
+    {
+        self.sync_data()
+    }
+
+    fn set_len(&mut self, len: u64) -> io::Result<()>
+    {
+        File::set_len(self, len)
+    }
+}
+
+/// 只在内存中的存储, sync 什么也不做
+impl Storage for Cursor<Vec<u8>>
+{
+    fn sync(&mut self) -> io::Result<()>
+    {
+        Ok(())
+    }
+
+    fn set_len(&mut self, len: u64) -> io::Result<()>
+    {
+        self.get_mut().resize(len as usize, 0);
+        Ok(())
+    }
+}
+
+/// 文件头中保存的树的状态, 也是日志提交记录的一部分
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct Header
+{
+    pub(crate) root: PageId,
+    pub(crate) len: u64,
+    pub(crate) page_count: u32,
+    pub(crate) free_head: PageId
+}
+
+impl Codec for Header
+{
+    fn encode(&self, out: &mut Vec<u8>)
+    {
+        self.root.encode(out);
+        self.len.encode(out);
+        self.page_count.encode(out);
+        self.free_head.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Option<Self>
+    {
+        Some(Self{ root: PageId::decode(input)?, len: u64::decode(input)?, page_count: u32::decode(input)?, free_head: PageId::decode(input)? })
+    }
+}
+
+struct PagedNode<K, V>
+{
+    keys: InlineVec<K, RANK>,
+    vals: InlineVec<V, RANK>,
+    /// 叶子节点为空
+    children: InlineVec<PageId, { RANK + 1 }>
+}
+
+impl<K, V> PagedNode<K,V>
+{
+    const fn new() -> Self
+    {
+        Self{ keys: InlineVec::new(), vals: InlineVec::new(), children: InlineVec::new() }
+    }
+
+    fn is_leaf(&self) -> bool
+    {
+        self.children.is_empty()
+    }
+}
+
+impl<K:Codec, V: Codec> PagedNode<K,V>
+{
+    /// 编码成一整页, 不足的部分补 0
+    fn encode(&self, page: &mut Vec<u8>)
+    {
+        page.clear();
+        (self.keys.len() as u16).encode(page);
+        self.is_leaf().encode(page);
+        self.children.iter().for_each(|child| child.encode(page));
+        for (key, value) in self.keys.iter().zip(self.vals.iter()) {
+            key.encode(page);
+            value.encode(page);
+        }
+        debug_assert!(page.len() <= PAGE_SIZE);
+        page.resize(PAGE_SIZE, 0);
+    }
+
+    fn decode(mut page: &[u8]) -> io::Result<Self>
+    {
+        let input = &mut page;
+        let mut node = Self::new();
+        let len = u16::decode(input).map(usize::from).filter(|&len| len < RANK);
+        let (Some(len), Some(is_leaf)) = (len, bool::decode(input)) else { return Err(invalid_data("节点页头损坏")) };
+
+        if !is_leaf {
+            for _ in 0..=len {
+                node.children.push(PageId::decode(input).ok_or_else(|| invalid_data("节点页损坏"))?);
+            }
+        }
+        for _ in 0..len {
+            let (Some(key), Some(value)) = (K::decode(input), V::decode(input)) else { return Err(invalid_data("节点页损坏")) };
+            node.keys.push(key);
+            node.vals.push(value);
+        }
+        Ok(node)
+    }
+}
+
+/// 按页号读写底层文件, 并管理空闲页链表. 空闲页的开头 4 个字节是下一个空闲页的页号.
+///
+/// 当前操作修改的页在提交之前不能写入数据文件, 这些页暂存在 staged 中, 读取时优先使用.
+struct Pager<F>
+{
+    file: F,
+    page_count: u32,
+    free_head: PageId,
+    staged: HashMap<PageId, Vec<u8>>
+}
+
+impl<F: Storage> Pager<F>
+{
+    fn read_page(&mut self, id: PageId, page: &mut Vec<u8>) -> io::Result<()>
+    {
+        if let Some(staged) = self.staged.get(&id) {
+            page.clone_from(staged);
+            return Ok(());
+        }
+        page.resize(PAGE_SIZE, 0);
+        self.file.seek(SeekFrom::Start(id as u64 * PAGE_SIZE as u64))?;
+        self.file.read_exact(page)
+    }
+
+    fn write_page(&mut self, id: PageId, page: &[u8]) -> io::Result<()>
+    {
+        self.file.seek(SeekFrom::Start(id as u64 * PAGE_SIZE as u64))?;
+        self.file.write_all(page)
+    }
+
+    /// 优先复用空闲页, 否则在文件末尾追加一页
+    fn alloc(&mut self, page: &mut Vec<u8>) -> io::Result<PageId>
+    {
+        if self.free_head == 0 {
+            self.page_count += 1;
+            return Ok(self.page_count - 1);
+        }
+        let id = self.free_head;
+        self.read_page(id, page)?;
+        self.free_head = PageId::decode(&mut &page[..]).unwrap();
+        Ok(id)
+    }
+
+    /// 空闲链表的链接直接写成页面内容, 所以也要先暂存
+    fn release(&mut self, id: PageId)
+    {
+        let mut page = Vec::with_capacity(PAGE_SIZE);
+        self.free_head.encode(&mut page);
+        page.resize(PAGE_SIZE, 0);
+        self.staged.insert(id, page);
+        self.free_head = id;
+    }
+
+    fn write_header(&mut self, header: Header, page: &mut Vec<u8>) -> io::Result<()>
+    {
+        page.clear();
+        page.extend_from_slice(MAGIC);
+        (PAGE_SIZE as u32).encode(page);
+        header.encode(page);
+        page.resize(PAGE_SIZE, 0);
+        self.write_page(0, page)
+    }
+
+    /// 读取文件头. 全是 0 的文件头说明第一次检查点还没有完成, 当作空文件.
+    fn read_header(&mut self, page: &mut Vec<u8>) -> io::Result<Header>
+    {
+        self.read_page(0, page)?;
+        if page.iter().all(|&byte| byte == 0) { return Ok(Header{ page_count: 1, ..Header::default() }) }
+
+        let input = &mut &page[..];
+        if take_bytes(input, MAGIC.len()) != Some(MAGIC) { return Err(invalid_data("不是 PagedBtree 文件")) }
+        if u32::decode(input) != Some(PAGE_SIZE as u32) { return Err(invalid_data("页大小不匹配")) }
+        let header = Header::decode(input).unwrap();
+        if header.page_count == 0 || header.root >= header.page_count { return Err(invalid_data("文件头损坏")) }
+        Ok(header)
+    }
+}
+
+struct Frame<K, V>
+{
+    node: PagedNode<K,V>,
+    dirty: bool,
+    last_used: u64
+}
+
+/// 缓存解码后的节点. 缓存满时淘汰最久没有使用的节点, 被修改过的节点在淘汰时写回磁盘,
+/// 但是当前操作修改的节点只能暂存到 Pager 中, 等到日志提交之后才能写入数据文件.
+struct BufferPool<K, V, F>
+{
+    pager: Pager<F>,
+    frames: HashMap<PageId, Frame<K,V>>,
+    capacity: usize,
+    clock: u64,
+    /// 当前操作修改过的页
+    touched: HashSet<PageId>,
+    /// 操作中途出错后缓存的内容可能不完整, 之后拒绝所有访问, 需要重新打开以便从日志恢复
+    failed: bool,
+    /// 编码和读取页面时复用的缓冲区
+    page: Vec<u8>
+}
+
+impl<K:Codec, V: Codec, F: Storage> BufferPool<K,V,F>
+{
+    fn evict_if_full(&mut self) -> io::Result<()>
+    {
+        if self.frames.len() < self.capacity { return Ok(()) }
+        let (&id, _) = self.frames.iter().min_by_key(|(_, frame)| frame.last_used).unwrap();
+        let frame = self.frames.remove(&id).unwrap();
+        if frame.dirty {
+            frame.node.encode(&mut self.page);
+            if self.touched.contains(&id) {
+                self.pager.staged.insert(id, self.page.clone());
+            }
+            else {
+                self.pager.write_page(id, &self.page)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn frame(&mut self, id: PageId) -> io::Result<&mut Frame<K,V>>
+    {
+        if self.failed { return Err(io::Error::other("之前的操作失败, 需要重新打开")) }
+        self.clock += 1;
+        if !self.frames.contains_key(&id) {
+            self.evict_if_full()?;
+            self.pager.read_page(id, &mut self.page)?;
+            let node = PagedNode::decode(&self.page)?;
+            // 从暂存区读出的节点和数据文件中的不同
+            let dirty = self.pager.staged.contains_key(&id);
+            self.frames.insert(id, Frame{ node, dirty, last_used: 0 });
+        }
+        let frame = self.frames.get_mut(&id).unwrap();
+        frame.last_used = self.clock;
+        Ok(frame)
+    }
+
+    fn node(&mut self, id: PageId) -> io::Result<&PagedNode<K,V>>
+    {
+        Ok(&self.frame(id)?.node)
+    }
+
+    fn node_mut(&mut self, id: PageId) -> io::Result<&mut PagedNode<K,V>>
+    {
+        self.touched.insert(id);
+        let frame = self.frame(id)?;
+        frame.dirty = true;
+        Ok(&mut frame.node)
+    }
+
+    /// 把节点从缓存中取出来, 修改完之后必须用 put 放回. 需要同时修改几个节点时使用.
+    fn take(&mut self, id: PageId) -> io::Result<PagedNode<K,V>>
+    {
+        self.frame(id)?;
+        Ok(self.frames.remove(&id).unwrap().node)
+    }
+
+    fn put(&mut self, id: PageId, node: PagedNode<K,V>) -> io::Result<()>
+    {
+        self.touched.insert(id);
+        self.evict_if_full()?;
+        self.clock += 1;
+        self.frames.insert(id, Frame{ node, dirty: true, last_used: self.clock });
+        Ok(())
+    }
+
+    fn alloc(&mut self, node: PagedNode<K,V>) -> io::Result<PageId>
+    {
+        let id = self.pager.alloc(&mut self.page)?;
+        self.put(id, node)?;
+        Ok(id)
+    }
+
+    /// 丢弃节点 (它的成员已经被移走) 并把页放回空闲链表
+    fn release(&mut self, id: PageId)
+    {
+        self.touched.insert(id);
+        self.frames.remove(&id);
+        self.pager.release(id);
+    }
+
+    /// 把当前操作修改过的页写入日志并提交, 之后暂存的页就可以写入数据文件了
+    fn commit<W: Storage>(&mut self, wal: &mut Wal<W>, header: Header) -> io::Result<()>
+    {
+        let mut touched: Vec<_> = self.touched.drain().collect();
+        touched.sort_unstable();
+        for id in touched {
+            match self.frames.get(&id)
+            {
+                Some(frame) => {
+                    frame.node.encode(&mut self.page);
+                    wal.log_page(id, &self.page);
+                    // 节点还在缓存里, 以缓存中的为准
+                    self.pager.staged.remove(&id);
+                }
+                None => wal.log_page(id, &self.pager.staged[&id])
+            }
+        }
+        wal.commit(header)?;
+
+        for (id, page) in self.pager.staged.drain().collect::<Vec<_>>() {
+            self.pager.write_page(id, &page)?;
+        }
+        Ok(())
+    }
+
+    /// 把所有被修改的节点和文件头写入数据文件并 sync. 调用时不能有未提交的修改.
+    fn checkpoint(&mut self, header: Header) -> io::Result<()>
+    {
+        debug_assert!(self.touched.is_empty() && self.pager.staged.is_empty());
+        for (&id, frame) in self.frames.iter_mut().filter(|(_, frame)| frame.dirty) {
+            frame.node.encode(&mut self.page);
+            self.pager.write_page(id, &self.page)?;
+            frame.dirty = false;
+        }
+        self.pager.write_header(header, &mut self.page)?;
+        self.pager.file.sync()
+    }
+}
+
+/// 分裂、借用和合并的规则和 Btree 共用, 这里只负责通过缓冲池读写节点
+impl<K:Codec, V: Codec, F: Storage> NodeAccess for BufferPool<K,V,F>
+{
+    type Node = PageId;
+    type Error = io::Error;
+
+    fn len(&mut self, node: PageId) -> io::Result<usize>
+    {
+        Ok(self.node(node)?.keys.len())
+    }
+
+    fn child(&mut self, parent: PageId, idx: usize) -> io::Result<PageId>
+    {
+        Ok(self.node(parent)?.children[idx])
+    }
+}
+
+impl<K:Codec, V: Codec, F: Storage> Split for BufferPool<K,V,F>
+{
+    type Member = (K,V);
+
+    fn split_off(&mut self, node: PageId, mid: usize) -> io::Result<((K,V), PageId)>
+    {
+        let left = self.node_mut(node)?;
+        let right = PagedNode{
+            keys: left.keys.split_off(mid + 1),
+            vals: left.vals.split_off(mid + 1),
+            children: if left.is_leaf() { InlineVec::new() } else { left.children.split_off(mid + 1) }
+        };
+        let member = (left.keys.pop().unwrap(), left.vals.pop().unwrap());
+        Ok((member, self.alloc(right)?))
+    }
+
+    fn insert_child(&mut self, parent: PageId, idx: usize, (key, value): (K,V), right: PageId) -> io::Result<()>
+    {
+        let node = self.node_mut(parent)?;
+        node.keys.insert(idx, key);
+        node.vals.insert(idx, value);
+        node.children.insert(idx + 1, right);
+        Ok(())
+    }
+
+    fn new_root(&mut self, left: PageId, (key, value): (K,V), right: PageId) -> io::Result<PageId>
+    {
+        let mut root = PagedNode::new();
+        root.keys.push(key);
+        root.vals.push(value);
+        root.children.push(left);
+        root.children.push(right);
+        self.alloc(root)
+    }
+}
+
+impl<K:Codec, V: Codec, F: Storage> Rebalance for BufferPool<K,V,F>
+{
+    fn borrow_from_right(&mut self, parent: PageId, idx: usize) -> io::Result<()>
+    {
+        let mut parent_node = self.take(parent)?;
+        let mut right = self.take(parent_node.children[idx + 1])?;
+        let key = replace(&mut parent_node.keys[idx], right.keys.remove(0));
+        let value = replace(&mut parent_node.vals[idx], right.vals.remove(0));
+        let child = (!right.is_leaf()).then(|| right.children.remove(0));
+        self.put(parent_node.children[idx + 1], right)?;
+
+        let current = self.node_mut(parent_node.children[idx])?;
+        current.keys.push(key);
+        current.vals.push(value);
+        if let Some(child) = child { current.children.push(child); }
+        self.put(parent, parent_node)
+    }
+
+    fn borrow_from_left(&mut self, parent: PageId, idx: usize) -> io::Result<()>
+    {
+        let mut parent_node = self.take(parent)?;
+        let mut left = self.take(parent_node.children[idx - 1])?;
+        let key = replace(&mut parent_node.keys[idx - 1], left.keys.pop().unwrap());
+        let value = replace(&mut parent_node.vals[idx - 1], left.vals.pop().unwrap());
+        let child = left.children.pop();
+        self.put(parent_node.children[idx - 1], left)?;
+
+        let current = self.node_mut(parent_node.children[idx])?;
+        current.keys.insert(0, key);
+        current.vals.insert(0, value);
+        if let Some(child) = child { current.children.insert(0, child); }
+        self.put(parent, parent_node)
+    }
+
+    fn merge(&mut self, parent: PageId, idx: usize) -> io::Result<()>
+    {
+        let mut parent_node = self.take(parent)?;
+        let right_id = parent_node.children.remove(idx + 1);
+        let mut right = self.take(right_id)?;
+        let left = self.node_mut(parent_node.children[idx])?;
+        left.keys.push(parent_node.keys.remove(idx));
+        left.vals.push(parent_node.vals.remove(idx));
+        left.keys.append(&mut right.keys);
+        left.vals.append(&mut right.vals);
+        left.children.append(&mut right.children);
+        self.release(right_id);
+        self.put(parent, parent_node)
+    }
+}
+
+/// 检查日志组的逻辑记录: 内容要能解码成键值对或者键, 插入最多让长度加一, 删除最多减一.
+/// 上一组的长度未知时 (检查点之后的第一组) 只检查解码.
+fn check_operation<K:Codec, V: Codec>(operation: Option<&Operation>, previous_len: Option<u64>, len: u64) -> io::Result<()>
+{
+    let valid = match operation
+    {
+        None => true,
+        Some(Operation::Insert(entry)) => {
+            let input = &mut &entry[..];
+            K::decode(input).is_some() && V::decode(input).is_some() && input.is_empty()
+                && previous_len.is_none_or(|previous| len == previous || len == previous + 1)
+        }
+        Some(Operation::Remove(key)) => {
+            let input = &mut &key[..];
+            K::decode(input).is_some() && input.is_empty()
+                && previous_len.is_none_or(|previous| len == previous || len + 1 == previous)
+        }
+    };
+    if valid { Ok(()) } else { Err(invalid_data("日志中的操作和提交的文件头不一致")) }
+}
+
+/// 保存在文件中的 B 树. 每个节点占一页, 只有缓冲池中的节点才在内存里, 所以树可以远大于内存.
+///
+/// 节点之间用页号连接, 插入和删除沿用 Btree 的分裂、借用和合并规则, 但是用查找路径代替 parent 指针,
+/// 移动子节点时不需要改写子节点所在的页. 键和值通过 [`Codec`] 编码, 单个键值对编码后不能超过 [`MAX_ENTRY_SIZE`].
+///
+/// 每次 insert 和 remove 返回之前, 它修改的所有页都已经作为一组写入预写日志并 sync,
+/// 所以进程在任何时刻被杀死, 重新打开时都会恢复到某次操作完成之后的状态.
+/// 数据文件只在检查点时才保证和日志一致, 检查点之后清空日志.
+pub struct PagedBtree<K:Ord + Codec, V: Codec, F: Storage = File>
+{
+    pool: BufferPool<K,V,F>,
+    wal: Wal<F>,
+    root: PageId,
+    len: usize
+}
+
+impl<K:Ord + Codec, V: Codec> PagedBtree<K,V,File>
+{
+    /// 打开 path 处的树, 文件不存在或者为空时新建一棵树. 日志保存在 path 后面加上 "-wal" 的文件中.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self>
+    {
+        Self::open_with_capacity(path, DEFAULT_FRAMES)
+    }
+
+    /// 同 open, 缓冲池最多缓存 frames 个节点
+    pub fn open_with_capacity(path: impl AsRef<Path>, frames: usize) -> io::Result<Self>
+    {
+        let mut wal_path = path.as_ref().as_os_str().to_owned();
+        wal_path.push("-wal");
+        let open = |path: &Path| OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path);
+        Self::with_storage(open(path.as_ref())?, open(wal_path.as_ref())?, frames)
+    }
+}
+
+impl<K:Ord + Codec, V: Codec, F: Storage> PagedBtree<K,V,F>
+{
+    /// 在任意存储上打开树, 数据为空时新建一棵树. 日志中有已经提交的操作时先把它们重放到数据文件.
+    pub fn with_storage(mut file: F, wal: F, frames: usize) -> io::Result<Self>
+    {
+        assert!(frames > 0, "缓冲池至少要能缓存一个节点");
+        // 第一次检查点写文件头时崩溃, 文件可能不足一页
+        let no_header = file.seek(SeekFrom::End(0))? < PAGE_SIZE as u64;
+        let pager = Pager{ file, page_count: 1, free_head: 0, staged: HashMap::new() };
+        let mut pool = BufferPool{
+            pager, frames: HashMap::new(), capacity: frames, clock: 0, touched: HashSet::new(), failed: false, page: Vec::new()
+        };
+
+        // 日志中最后一次提交的文件头比数据文件中的新, 数据文件的文件头也可能在检查点时只写了一半
+        let mut wal = Wal::new(wal);
+        let groups = wal.committed_groups()?;
+        let header = match groups.last()
+        {
+            Some(group) => group.header,
+            None if no_header => Header{ page_count: 1, ..Header::default() },
+            None => pool.pager.read_header(&mut pool.page)?
+        };
+        // 先检查所有的组, 日志和 K, V 不符时不修改数据文件
+        let mut previous_len = None;
+        for group in &groups {
+            check_operation::<K,V>(group.operation.as_ref(), previous_len, group.header.len)?;
+            previous_len = Some(group.header.len);
+        }
+        for group in groups {
+            for (id, page) in group.pages {
+                pool.pager.write_page(id, &page)?;
+            }
+        }
+        pool.pager.page_count = header.page_count;
+        pool.pager.free_head = header.free_head;
+
+        let mut tree = Self{ pool, wal, root: header.root, len: header.len as usize };
+        tree.checkpoint()?;
+        if tree.root == 0 {
+            tree.root = tree.pool.alloc(PagedNode::new())?;
+            tree.commit()?;
+        }
+        Ok(tree)
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.len == 0
+    }
+
+    /// 文件占用的页数, 包括文件头和空闲页
+    pub fn page_count(&self) -> u32
+    {
+        self.pool.pager.page_count
+    }
+
+    fn header(&self) -> Header
+    {
+        Header{ root: self.root, len: self.len as u64, page_count: self.pool.pager.page_count, free_head: self.pool.pager.free_head }
+    }
+
+    /// 把缓冲池中被修改的节点和文件头写入数据文件并 sync, 然后清空日志
+    pub fn checkpoint(&mut self) -> io::Result<()>
+    {
+        if self.pool.failed { return Err(io::Error::other("之前的操作失败, 需要重新打开")) }
+        self.pool.checkpoint(self.header())?;
+        self.wal.truncate()
+    }
+
+    /// 提交当前操作修改的页, 日志太大时顺便做检查点
+    fn commit(&mut self) -> io::Result<()>
+    {
+        if self.pool.touched.is_empty() {
+            self.wal.discard();
+            return Ok(());
+        }
+        let header = self.header();
+        self.pool.commit(&mut self.wal, header)?;
+        if self.wal.len() > CHECKPOINT_WAL_SIZE {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// 操作成功时提交, 失败时丢弃日志记录并拒绝之后的操作.
+    /// 提交记录已经写入但后续写入失败时也返回错误, 重新打开后这次操作是生效的.
+    fn finish<T>(&mut self, result: io::Result<T>) -> io::Result<T>
+    {
+        let result = result.and_then(|value| self.commit().map(|()| value));
+        if result.is_err() {
+            self.pool.failed = true;
+            self.wal.discard();
+        }
+        result
+    }
+
+    /// 从根节点向下查找. 返回找到的 (页号, 下标) 或者应该插入的叶子位置, 以及经过的 (父节点, 子节点下标) 路径
+    fn search(&mut self, key: &K, path: &mut Vec<(PageId, usize)>) -> io::Result<Result<(PageId, usize), (PageId, usize)>>
+    {
+        let mut id = self.root;
+        loop {
+            let node = self.pool.node(id)?;
+            match search_keys(&node.keys, key)
+            {
+                Ok(idx) => return Ok(Ok((id, idx))),
+                Err(idx) if node.is_leaf() => return Ok(Err((id, idx))),
+                Err(idx) => {
+                    path.push((id, idx));
+                    id = node.children[idx];
+                }
+            }
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> io::Result<Option<V>>
+    where V: Clone
+    {
+        Ok(match self.search(key, &mut Vec::new())? {
+            Ok((id, idx)) => Some(self.pool.node(id)?.vals[idx].clone()),
+            Err(_) => None
+        })
+    }
+
+    pub fn contains_key(&mut self, key: &K) -> io::Result<bool>
+    {
+        Ok(self.search(key, &mut Vec::new())?.is_ok())
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> io::Result<Option<V>>
+    {
+        let page = &mut self.pool.page;
+        page.clear();
+        key.encode(page);
+        value.encode(page);
+        if page.len() > MAX_ENTRY_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "键值对编码后超过单页能容纳的大小"));
+        }
+        self.wal.log_insert(page);
+
+        let result = self.insert_pages(key, value);
+        self.finish(result)
+    }
+
+    fn insert_pages(&mut self, key: K, value: V) -> io::Result<Option<V>>
+    {
+        let mut path = Vec::new();
+        let (id, idx) = match self.search(&key, &mut path)? {
+            Ok((id, idx)) => return Ok(Some(replace(&mut self.pool.node_mut(id)?.vals[idx], value))),
+            Err(position) => position
+        };
+
+        let node = self.pool.node_mut(id)?;
+        node.keys.insert(idx, key);
+        node.vals.insert(idx, value);
+        self.len += 1;
+
+        if let Some(root) = split(&mut self.pool, id, &path)? {
+            self.root = root;
+        }
+        Ok(None)
+    }
+
+    pub fn remove(&mut self, key: &K) -> io::Result<Option<(K,V)>>
+    {
+        let page = &mut self.pool.page;
+        page.clear();
+        key.encode(page);
+        self.wal.log_remove(page);
+
+        let result = self.remove_pages(key);
+        self.finish(result)
+    }
+
+    fn remove_pages(&mut self, key: &K) -> io::Result<Option<(K,V)>>
+    {
+        let mut path = Vec::new();
+        let Ok((id, idx)) = self.search(key, &mut path)? else { return Ok(None) };
+
+        let (current, removed) = if self.pool.node(id)?.is_leaf() {
+            let node = self.pool.node_mut(id)?;
+            (id, (node.keys.remove(idx), node.vals.remove(idx)))
+        }
+        else {
+            // 用右子树最左边叶子的第一个成员替换被删除的成员
+            path.push((id, idx + 1));
+            let mut leaf = self.pool.node(id)?.children[idx + 1];
+            while !self.pool.node(leaf)?.is_leaf() {
+                path.push((leaf, 0));
+                leaf = self.pool.node(leaf)?.children[0];
+            }
+            let leaf_node = self.pool.node_mut(leaf)?;
+            let (key, value) = (leaf_node.keys.remove(0), leaf_node.vals.remove(0));
+            let node = self.pool.node_mut(id)?;
+            (leaf, (replace(&mut node.keys[idx], key), replace(&mut node.vals[idx], value)))
+        };
+        self.len -= 1;
+
+        rebalance(&mut self.pool, current, &path)?;
+
+        let root = self.pool.node(self.root)?;
+        if root.keys.is_empty() && !root.is_leaf() {
+            let old_root = replace(&mut self.root, root.children[0]);
+            self.pool.release(old_root);
+        }
+        Ok(Some(removed))
+    }
+}
+
+impl<K:Ord + Codec + Clone, V: Codec + Clone, F: Storage> PagedBtree<K,V,F>
+{
+    /// 按键的顺序遍历, 需要时从磁盘读取节点
+    pub fn iter(&mut self) -> Iter<'_, K,V,F>
+    {
+        Iter{ stack: vec![(self.root, 0)], descend: true, tree: self }
+    }
+}
+
+/// 中序遍历迭代器. 节点可能随时被缓冲池淘汰, 所以栈里只保存页号和下一个要返回的成员下标.
+pub struct Iter<'a, K:Ord + Codec, V: Codec, F: Storage>
+{
+    tree: &'a mut PagedBtree<K,V,F>,
+    stack: Vec<(PageId, usize)>,
+    /// 栈顶的节点还没有沿最左边的子节点向下走到叶子
+    descend: bool
+}
+
+impl<K:Ord + Codec + Clone, V: Codec + Clone, F: Storage> Iterator for Iter<'_, K,V,F>
+{
+    type Item = io::Result<(K,V)>;
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        let result = (|| {
+            loop {
+                let Some(&(id, idx)) = self.stack.last() else { return Ok(None) };
+                let node = self.tree.pool.node(id)?;
+                if self.descend && !node.is_leaf() {
+                    self.stack.push((node.children[idx], 0));
+                    continue;
+                }
+                if idx < node.keys.len() {
+                    let entry = (node.keys[idx].clone(), node.vals[idx].clone());
+                    self.stack.last_mut().unwrap().1 += 1;
+                    self.descend = !node.is_leaf();
+                    if self.descend { self.stack.push((node.children[idx + 1], 0)); }
+                    return Ok(Some(entry));
+                }
+                self.stack.pop();
+                self.descend = false;
+            }
+        })();
+        result.transpose()
+    }
+}
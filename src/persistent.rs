@@ -0,0 +1,325 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem::replace;
+use core::ops::Index;
+
+use crate::error::key_not_found;
+use crate::inline_vec::InlineVec;
+use crate::search::search_keys;
+use crate::RANK;
+
+/// 成员数少于 MIN_LEN 的非根节点需要向兄弟借成员或者合并
+const MIN_LEN: usize = RANK.div_ceil(2) - 1;
+
+#[derive(Clone)]
+struct PersistentNode<K, V>
+{
+    keys: InlineVec<K, RANK>,
+    vals: InlineVec<V, RANK>,
+    /// 叶子节点为空
+    children: InlineVec<Arc<Self>, { RANK + 1 }>
+}
+
+impl<K, V> PersistentNode<K,V>
+{
+    const fn new() -> Self
+    {
+        Self{ keys: InlineVec::new(), vals: InlineVec::new(), children: InlineVec::new() }
+    }
+
+    fn is_leaf(&self) -> bool
+    {
+        self.children.is_empty()
+    }
+}
+
+/// 不可变的持久化 B 树. insert 和 remove 返回新的树, 新旧两棵树通过 Arc 共享没有被修改的节点,
+/// 每次修改只复制从根节点到目标节点的一条路径. 克隆和 snapshot 都是 O(1) 的,
+/// 在 K 和 V 满足 Send + Sync 时可以把句柄交给其他线程读取.
+pub struct PersistentBtree<K:Ord, V>
+{
+    root: Arc<PersistentNode<K,V>>,
+    len: usize
+}
+
+impl<K:Ord, V> Clone for PersistentBtree<K,V>
+{
+    fn clone(&self) -> Self {
+        Self{ root: Arc::clone(&self.root), len: self.len }
+    }
+}
+
+impl<K:Ord, V> Default for PersistentBtree<K,V>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K:Ord, V> PersistentBtree<K,V>
+{
+    pub fn new() -> Self
+    {
+        Self{ root: Arc::new(PersistentNode::new()), len: 0 }
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.len == 0
+    }
+
+    /// 当前版本的只读句柄, 只增加根节点的引用计数
+    pub fn snapshot(&self) -> Self
+    {
+        self.clone()
+    }
+
+    /// 两个句柄是否共享同一个根节点, 即是否是同一个版本
+    pub fn ptr_eq(&self, other: &Self) -> bool
+    {
+        Arc::ptr_eq(&self.root, &other.root)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V>
+    {
+        let mut node = &*self.root;
+        loop {
+            match search_keys(&node.keys, key)
+            {
+                Ok(idx) => return Some(&node.vals[idx]),
+                Err(_) if node.is_leaf() => return None,
+                Err(idx) => node = &node.children[idx]
+            }
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool
+    {
+        self.get(key).is_some()
+    }
+
+    pub fn iter(&self) -> Iter<'_, K,V>
+    {
+        let mut iter = Iter{ stack: Vec::new(), remaining: self.len };
+        iter.push_left_edge(&self.root);
+        iter
+    }
+}
+
+impl<K:Ord + Clone, V: Clone> PersistentBtree<K,V>
+{
+    /// 返回插入了 (key, value) 的新版本, self 保持不变
+    pub fn insert(&self, key: K, value: V) -> Self
+    {
+        let mut tree = self.clone();
+        tree.insert_in_place(key, value);
+        tree
+    }
+
+    /// 返回删除了 key 的新版本, self 保持不变. key 不存在时返回的树和 self 共享根节点.
+    pub fn remove(&self, key: &K) -> Self
+    {
+        let mut tree = self.clone();
+        tree.remove_in_place(key);
+        tree
+    }
+
+    /// 在本句柄上插入, 只复制被其他版本共享的节点. 返回被替换的旧值.
+    pub fn insert_in_place(&mut self, key: K, value: V) -> Option<V>
+    {
+        let (old, split) = insert_into(&mut self.root, key, value);
+        if let Some((key, value, right)) = split {
+            let mut new_root = PersistentNode::new();
+            new_root.keys.push(key);
+            new_root.vals.push(value);
+            new_root.children.push(replace(&mut self.root, Arc::new(PersistentNode::new())));
+            new_root.children.push(right);
+            self.root = Arc::new(new_root);
+        }
+        if old.is_none() { self.len += 1; }
+        old
+    }
+
+    /// 在本句柄上删除, 只复制被其他版本共享的节点. 返回被删除的成员.
+    pub fn remove_in_place(&mut self, key: &K) -> Option<(K,V)>
+    {
+        // 先确认键存在, 避免为不存在的键复制路径
+        self.get(key)?;
+        let removed = remove_from(&mut self.root, key);
+
+        if self.root.keys.is_empty() && !self.root.is_leaf() {
+            self.root = Arc::clone(&self.root.children[0]);
+        }
+        // Ord 不合法时前后两次查找的结果可能不同
+        if removed.is_some() { self.len -= 1; }
+        removed
+    }
+}
+
+/// 沿着查找路径插入, 路径上的节点通过 Arc::make_mut 复制. 节点分裂时返回上移的成员和新的右节点.
+#[allow(clippy::type_complexity)]
+fn insert_into<K:Ord + Clone, V: Clone>(node: &mut Arc<PersistentNode<K,V>>, key: K, value: V) -> (Option<V>, Option<(K, V, Arc<PersistentNode<K,V>>)>)
+{
+    let node = Arc::make_mut(node);
+    match search_keys(&node.keys, &key)
+    {
+        Ok(idx) => return (Some(replace(&mut node.vals[idx], value)), None),
+        Err(idx) if node.is_leaf() => {
+            node.keys.insert(idx, key);
+            node.vals.insert(idx, value);
+        }
+        Err(idx) => {
+            let (old, split) = insert_into(&mut node.children[idx], key, value);
+            let Some((key, value, right)) = split else { return (old, None) };
+            node.keys.insert(idx, key);
+            node.vals.insert(idx, value);
+            node.children.insert(idx + 1, right);
+        }
+    }
+
+    if node.keys.len() < RANK { return (None, None) }
+
+    let mid = RANK.div_ceil(2) - 1;
+    let right = PersistentNode{
+        keys: node.keys.split_off(mid + 1),
+        vals: node.vals.split_off(mid + 1),
+        children: if node.is_leaf() { InlineVec::new() } else { node.children.split_off(mid + 1) }
+    };
+    let (key, value) = (node.keys.pop().unwrap(), node.vals.pop().unwrap());
+    (None, Some((key, value, Arc::new(right))))
+}
+
+fn remove_from<K:Ord + Clone, V: Clone>(node: &mut Arc<PersistentNode<K,V>>, key: &K) -> Option<(K,V)>
+{
+    let node = Arc::make_mut(node);
+    match search_keys(&node.keys, key)
+    {
+        Ok(idx) if node.is_leaf() => Some((node.keys.remove(idx), node.vals.remove(idx))),
+        Ok(idx) => {
+            // 和 Btree 一样用后继成员替换被删除的成员
+            let (k, v) = remove_first(&mut node.children[idx + 1]);
+            let removed = (replace(&mut node.keys[idx], k), replace(&mut node.vals[idx], v));
+            fix_child(node, idx + 1);
+            Some(removed)
+        }
+        Err(_) if node.is_leaf() => None,
+        Err(idx) => {
+            let removed = remove_from(&mut node.children[idx], key);
+            fix_child(node, idx);
+            removed
+        }
+    }
+}
+
+fn remove_first<K:Ord + Clone, V: Clone>(node: &mut Arc<PersistentNode<K,V>>) -> (K,V)
+{
+    let node = Arc::make_mut(node);
+    if node.is_leaf() { return (node.keys.remove(0), node.vals.remove(0)) }
+    let removed = remove_first(&mut node.children[0]);
+    fix_child(node, 0);
+    removed
+}
+
+/// children[idx] 的成员太少时向兄弟借一个成员, 兄弟也不够时和兄弟合并
+fn fix_child<K:Ord + Clone, V: Clone>(node: &mut PersistentNode<K,V>, idx: usize)
+{
+    if node.children[idx].keys.len() >= MIN_LEN { return }
+
+    if idx + 1 < node.children.len() && node.children[idx + 1].keys.len() > MIN_LEN {
+        let right = Arc::make_mut(&mut node.children[idx + 1]);
+        let (key, value) = (right.keys.remove(0), right.vals.remove(0));
+        let child = (!right.is_leaf()).then(|| right.children.remove(0));
+        let key = replace(&mut node.keys[idx], key);
+        let value = replace(&mut node.vals[idx], value);
+
+        let current = Arc::make_mut(&mut node.children[idx]);
+        current.keys.push(key);
+        current.vals.push(value);
+        if let Some(child) = child { current.children.push(child); }
+    }
+    else if idx > 0 && node.children[idx - 1].keys.len() > MIN_LEN {
+        let left = Arc::make_mut(&mut node.children[idx - 1]);
+        let (key, value) = (left.keys.pop().unwrap(), left.vals.pop().unwrap());
+        let child = left.children.pop();
+        let key = replace(&mut node.keys[idx - 1], key);
+        let value = replace(&mut node.vals[idx - 1], value);
+
+        let current = Arc::make_mut(&mut node.children[idx]);
+        current.keys.insert(0, key);
+        current.vals.insert(0, value);
+        if let Some(child) = child { current.children.insert(0, child); }
+    }
+    else {
+        // 把右边的节点合并到左边的节点
+        let left_idx = if idx + 1 < node.children.len() { idx } else { idx - 1 };
+        let (key, value) = (node.keys.remove(left_idx), node.vals.remove(left_idx));
+        let right = node.children.remove(left_idx + 1);
+        let mut right = Arc::try_unwrap(right).unwrap_or_else(|shared| (*shared).clone());
+
+        let left = Arc::make_mut(&mut node.children[left_idx]);
+        left.keys.push(key);
+        left.vals.push(value);
+        left.keys.append(&mut right.keys);
+        left.vals.append(&mut right.vals);
+        left.children.append(&mut right.children);
+    }
+}
+
+/// 用栈代替 parent 指针的中序遍历迭代器, 栈里保存节点和下一个要返回的成员下标
+pub struct Iter<'a, K, V>
+{
+    stack: Vec<(&'a PersistentNode<K,V>, usize)>,
+    remaining: usize
+}
+
+impl<'a, K, V> Iter<'a, K,V>
+{
+    fn push_left_edge(&mut self, mut node: &'a PersistentNode<K,V>)
+    {
+        loop {
+            self.stack.push((node, 0));
+            if node.is_leaf() { return }
+            node = &node.children[0];
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K,V>
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        loop {
+            let (node, idx) = *self.stack.last()?;
+            if idx < node.keys.len() {
+                self.stack.last_mut().unwrap().1 += 1;
+                if !node.is_leaf() { self.push_left_edge(&node.children[idx + 1]); }
+                self.remaining -= 1;
+                return Some((&node.keys[idx], &node.vals[idx]));
+            }
+            self.stack.pop();
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K:Ord, V> Index<K> for PersistentBtree<K,V>
+{
+    type Output = V;
+
+    fn index(&self, index: K) -> &Self::Output {
+        match self.get(&index) {
+            None => key_not_found(),
+            Some(val) => val
+        }
+    }
+}
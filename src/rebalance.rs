@@ -0,0 +1,134 @@
+use crate::RANK;
+
+/// 成员数少于 MIN_LEN 的非根节点需要向兄弟借成员或者合并
+pub(crate) const MIN_LEN: usize = RANK.div_ceil(2) - 1;
+
+/// 插入和删除之后调整树的结构需要的节点操作. Btree 通过指针直接访问节点, PagedBtree 通过缓冲池访问页面,
+/// 两者用同一个 [`split`] 决定怎样分裂, 用同一个 [`rebalance`] 决定向哪个兄弟借成员、什么时候合并.
+pub(crate) trait NodeAccess
+{
+    /// 节点的句柄, 指针或者页号
+    type Node: Copy;
+    type Error;
+
+    /// 节点的成员数
+    fn len(&mut self, node: Self::Node) -> Result<usize, Self::Error>;
+
+    /// parent 的第 idx 个子节点
+    fn child(&mut self, parent: Self::Node, idx: usize) -> Result<Self::Node, Self::Error>;
+
+    /// 节点的成员或者子节点变化了, 调用时它的子节点都已经处理过. 默认什么都不做, Btree 用它记录要更新附加信息的节点
+    fn changed(&mut self, _node: Self::Node) -> Result<(), Self::Error>
+    {
+        Ok(())
+    }
+}
+
+/// 插入之后分裂节点需要的操作
+pub(crate) trait Split: NodeAccess
+{
+    /// 从分裂的节点上移到父节点的成员
+    type Member;
+
+    /// 把 node 第 mid 个成员之后的成员和子节点移到一个新的右节点, 返回第 mid 个成员和新节点. node 只保留前 mid 个成员
+    fn split_off(&mut self, node: Self::Node, mid: usize) -> Result<(Self::Member, Self::Node), Self::Error>;
+
+    /// 在 parent 的第 idx 个位置插入成员, 并把 right 作为它右边的子节点
+    fn insert_child(&mut self, parent: Self::Node, idx: usize, member: Self::Member, right: Self::Node) -> Result<(), Self::Error>;
+
+    /// 创建只有一个成员的新根节点, 两个子节点是 left 和 right
+    fn new_root(&mut self, left: Self::Node, member: Self::Member, right: Self::Node) -> Result<Self::Node, Self::Error>;
+}
+
+/// 删除之后修复成员过少的节点需要的操作
+pub(crate) trait Rebalance: NodeAccess
+{
+    /// 右兄弟的第一个成员移到父节点, 父节点中的分隔成员移到 parent 的第 idx 个子节点的末尾, 右兄弟的第一个子节点也跟过去
+    fn borrow_from_right(&mut self, parent: Self::Node, idx: usize) -> Result<(), Self::Error>;
+
+    /// 和 borrow_from_right 对称, 从左兄弟的末尾借一个成员放到开头
+    fn borrow_from_left(&mut self, parent: Self::Node, idx: usize) -> Result<(), Self::Error>;
+
+    /// 把 parent 的第 idx + 1 个子节点和它们之间的分隔成员合并进第 idx 个子节点, 然后释放右边的节点
+    fn merge(&mut self, parent: Self::Node, idx: usize) -> Result<(), Self::Error>;
+}
+
+/// 从刚插入了成员的 current 开始向上分裂满了的节点. path 是从根节点到 current 的父节点的路径, 每项是节点和下降时走的子节点下标.
+/// 成员数达到 RANK 的节点左边保留 MIN_LEN 个成员, 下一个成员上移到父节点, 其余的移到新的右节点, 父节点因此满了时继续向上.
+/// 分裂出来的两个节点、停下来的节点和它的所有祖先都会自底向上交给 changed. 根节点也分裂时返回新的根节点.
+pub(crate) fn split<T: Split>(tree: &mut T, mut current: T::Node, path: &[(T::Node, usize)]) -> Result<Option<T::Node>, T::Error>
+{
+    let mut ancestors = path.iter().rev();
+    while tree.len(current)? == RANK {
+        let (member, right) = tree.split_off(current, MIN_LEN)?;
+        tree.changed(current)?;
+        tree.changed(right)?;
+        match ancestors.next()
+        {
+            Some(&(parent, idx)) => {
+                tree.insert_child(parent, idx, member, right)?;
+                current = parent;
+            }
+            None => {
+                let root = tree.new_root(current, member, right)?;
+                tree.changed(root)?;
+                return Ok(Some(root))
+            }
+        }
+    }
+
+    tree.changed(current)?;
+    for &(ancestor, _) in ancestors {
+        tree.changed(ancestor)?;
+    }
+    Ok(None)
+}
+
+/// 从 current 开始向上修复成员过少的节点. path 是从根节点到 current 的父节点的路径, 每项是节点和下降时走的子节点下标.
+/// 先向右兄弟、再向左兄弟借一个成员, 兄弟都只有 MIN_LEN 个成员时和兄弟合并, 父节点因此变少时继续向上.
+/// 被修改的节点和 current 的所有祖先都会自底向上交给 changed.
+/// 一直合并到根节点时返回根节点, 它可能已经没有成员, 由调用者换成它唯一的子节点.
+pub(crate) fn rebalance<T: Rebalance>(tree: &mut T, mut current: T::Node, path: &[(T::Node, usize)])
+    -> Result<Option<T::Node>, T::Error>
+{
+    let mut ancestors = path.iter().rev();
+    let mut root = None;
+    while tree.len(current)? < MIN_LEN {
+        let Some(&(parent, idx)) = ancestors.next() else {
+            root = Some(current);
+            break
+        };
+
+        let has_right = idx < tree.len(parent)?;
+        let right = if has_right { Some(tree.child(parent, idx + 1)?) } else { None };
+        let left = if idx > 0 { Some(tree.child(parent, idx - 1)?) } else { None };
+        // 借用时被共享的兄弟节点会换成复制出来的节点, 所以借用之后重新读取兄弟节点
+        if let Some(right) = right && tree.len(right)? > MIN_LEN {
+            tree.borrow_from_right(parent, idx)?;
+            let right = tree.child(parent, idx + 1)?;
+            tree.changed(right)?;
+            tree.changed(current)?;
+            current = parent;
+            break
+        }
+        if let Some(left) = left && tree.len(left)? > MIN_LEN {
+            tree.borrow_from_left(parent, idx)?;
+            let left = tree.child(parent, idx - 1)?;
+            tree.changed(left)?;
+            tree.changed(current)?;
+            current = parent;
+            break
+        }
+        let left_idx = if has_right { idx } else { idx - 1 };
+        tree.merge(parent, left_idx)?;
+        let merged = tree.child(parent, left_idx)?;
+        tree.changed(merged)?;
+        current = parent;
+    }
+
+    tree.changed(current)?;
+    for &(ancestor, _) in ancestors {
+        tree.changed(ancestor)?;
+    }
+    Ok(root)
+}
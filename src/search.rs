@@ -0,0 +1,22 @@
+use core::cmp::Ordering;
+
+/// 在单个节点的键数组中查找, 找到返回 Ok(下标), 找不到返回 Err(应该插入或者下降的位置).
+/// 每个键只调用一次 Ord::cmp, 不使用 PartialOrd 和 PartialEq, 所以 Ord 不合法时返回的下标也不会超过 keys.len().
+pub(crate) fn search_keys<K:Ord>(keys: &[K], key: &K) -> Result<usize, usize>
+{
+    for (idx, k) in keys.iter().enumerate() {
+        match k.cmp(key)
+        {
+            Ordering::Less => {}
+            Ordering::Equal => return Ok(idx),
+            Ordering::Greater => return Err(idx)
+        }
+    }
+    Err(keys.len())
+}
+
+/// 第一个大于等于 key (inclusive 为 false 时是大于 key) 的键的下标, 没有时返回 keys.len()
+pub(crate) fn bound_index<K:Ord>(keys: &[K], key: &K, inclusive: bool) -> usize
+{
+    keys.iter().position(|k| if inclusive { k.cmp(key).is_ge() } else { k.cmp(key).is_gt() }).unwrap_or(keys.len())
+}
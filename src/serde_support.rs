@@ -0,0 +1,68 @@
+//! `serde` feature 打开时 Btree 的序列化支持. Btree 序列化为按键排序的 map,
+//! 反序列化时如果输入的键已经严格递增就直接自底向上构建树, 否则逐个插入, 重复的键保留最后一个值.
+//! 需要拒绝重复或者乱序的键时, 用 `#[serde(deserialize_with = "naive_btree::serde_support::deserialize_strict")]`.
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
+
+use serde::de::{Deserialize, Deserializer, Error, MapAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+use crate::error::check_increasing;
+use crate::{Allocator, Btree};
+
+impl<K:Ord + Serialize, V: Serialize, A: Allocator> Serialize for Btree<K,V,A>
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+struct BtreeVisitor<K, V>
+{
+    strict: bool,
+    _marker: PhantomData<(K, V)>
+}
+
+impl<'de, K:Ord + Deserialize<'de>, V: Deserialize<'de>> Visitor<'de> for BtreeVisitor<K,V>
+{
+    type Value = Btree<K,V>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(if self.strict { "a map with strictly increasing keys" } else { "a map" })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        // 不完全相信输入给出的长度
+        let mut entries: Vec<(K,V)> = Vec::with_capacity(map.size_hint().unwrap_or(0).min(4096));
+        while let Some((key, value)) = map.next_entry()? {
+            if let Some(Err(err)) = entries.last().map(|(last, _)| check_increasing(last, &key)) {
+                if self.strict { return Err(A::Error::custom(err)) }
+                // 之前的部分是有序的, 仍然直接构建, 剩下的键值对逐个插入
+                let mut tree = Btree::build_sorted(entries);
+                tree.insert(key, value);
+                while let Some((key, value)) = map.next_entry()? {
+                    tree.insert(key, value);
+                }
+                return Ok(tree);
+            }
+            entries.push((key, value));
+        }
+        Ok(Btree::build_sorted(entries))
+    }
+}
+
+impl<'de, K:Ord + Deserialize<'de>, V: Deserialize<'de>> Deserialize<'de> for Btree<K,V>
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(BtreeVisitor{ strict: false, _marker: PhantomData })
+    }
+}
+
+/// 严格模式的反序列化, 输入中有重复或者不是递增的键时返回错误
+pub fn deserialize_strict<'de, D, K, V>(deserializer: D) -> Result<Btree<K,V>, D::Error>
+where D: Deserializer<'de>, K: Ord + Deserialize<'de>, V: Deserialize<'de>
+{
+    deserializer.deserialize_map(BtreeVisitor{ strict: true, _marker: PhantomData })
+}
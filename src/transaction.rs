@@ -0,0 +1,115 @@
+use alloc::vec::Vec;
+use core::mem::take;
+use core::ops::Deref;
+
+use crate::{Allocator, Btree, Global};
+
+/// 回滚一次修改需要的信息
+enum Undo<K, V>
+{
+    /// 插入了新的键, 回滚时删除它
+    Inserted(K),
+    /// 键原来的值被替换
+    Replaced(K, V),
+    /// 键值对被删除
+    Removed(K, V)
+}
+
+/// [`Btree::transaction`] 返回的事务. 通过它做的修改会记入撤销日志,
+/// 调用 commit 之后才保留, 调用 rollback 或者没有 commit 就被 drop (包括 panic 和用 ? 提前返回) 时,
+/// 按相反的顺序撤销所有修改, 树恢复为事务开始之前的内容.
+///
+/// insert 和 remove 把 Btree 返回的旧值和被删除的键值对移进日志, 返回指向日志中它们的引用, 所以 V 不需要 Clone.
+/// 读取可以直接通过 Deref 使用 Btree 的方法.
+///
+/// 撤销也是插入和删除, 会调用 K 的比较, 有快照时还会 clone 节点. 在 panic 展开时回滚而它们再次 panic, 有 std feature 时回滚就此停止,
+/// 树停留在撤销到一半的状态; 没有 std 时无法检测展开, 进程会中止.
+pub struct Transaction<'a, K:Ord + Clone, V, A: Allocator = Global>
+{
+    tree: &'a mut Btree<K,V,A>,
+    undo: Vec<Undo<K,V>>
+}
+
+impl<K:Ord + Clone, V, A: Allocator> Btree<K,V,A>
+{
+    pub fn transaction(&mut self) -> Transaction<'_, K,V,A>
+    {
+        Transaction{ tree: self, undo: Vec::new() }
+    }
+}
+
+impl<K:Ord + Clone, V, A: Allocator> Transaction<'_, K,V,A>
+{
+    /// 同 Btree::insert, 返回键原来的值. 旧值保存在撤销日志中, 提交或者回滚时才被析构
+    pub fn insert(&mut self, key: K, value: V) -> Option<&V>
+    {
+        let undo_key = key.clone();
+        self.undo.push(match self.tree.insert(key, value)
+        {
+            Some(old) => Undo::Replaced(undo_key, old),
+            None => Undo::Inserted(undo_key)
+        });
+        match self.undo.last()
+        {
+            Some(Undo::Replaced(_, old)) => Some(old),
+            _ => None
+        }
+    }
+
+    /// 同 Btree::remove, 返回被删除的键值对. 它保存在撤销日志中, 提交或者回滚时才被析构
+    pub fn remove(&mut self, key: &K) -> Option<(&K, &V)>
+    {
+        let (key, value) = self.tree.remove(key)?;
+        self.undo.push(Undo::Removed(key, value));
+        match self.undo.last()
+        {
+            Some(Undo::Removed(key, value)) => Some((key, value)),
+            _ => unreachable!()
+        }
+    }
+
+    /// 保留所有修改, 丢弃撤销日志
+    pub fn commit(mut self)
+    {
+        self.undo.clear();
+    }
+
+    /// 撤销所有修改
+    pub fn rollback(mut self)
+    {
+        self.undo_all();
+    }
+
+    fn undo_all(&mut self)
+    {
+        for undo in take(&mut self.undo).into_iter().rev() {
+            match undo
+            {
+                Undo::Inserted(key) => { self.tree.remove(&key); }
+                Undo::Replaced(key, value) | Undo::Removed(key, value) => { self.tree.insert(key, value); }
+            }
+        }
+    }
+}
+
+impl<K:Ord + Clone, V, A: Allocator> Deref for Transaction<'_, K,V,A>
+{
+    type Target = Btree<K,V,A>;
+
+    fn deref(&self) -> &Btree<K,V,A> {
+        self.tree
+    }
+}
+
+impl<K:Ord + Clone, V, A: Allocator> Drop for Transaction<'_, K,V,A>
+{
+    fn drop(&mut self) {
+        // 展开时撤销再次 panic 会中止进程, 所以捕获它并停止回滚
+        #[cfg(feature = "std")]
+        if std::thread::panicking() {
+            let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| self.undo_all()));
+            return;
+        }
+        self.undo_all();
+    }
+}
@@ -0,0 +1,140 @@
+use std::io::{self, SeekFrom};
+use std::mem::take;
+use std::vec::Vec;
+
+use crate::codec::{checksum, take_bytes, Codec};
+use crate::paged::{Header, PageId, Storage, PAGE_SIZE};
+
+const RECORD_INSERT: u8 = 1;
+const RECORD_REMOVE: u8 = 2;
+const RECORD_PAGE: u8 = 3;
+const RECORD_COMMIT: u8 = 4;
+
+/// 组开头的逻辑记录, 内容是编码后的键值对或者键
+pub(crate) enum Operation
+{
+    Insert(Vec<u8>),
+    Remove(Vec<u8>)
+}
+
+/// 一次提交的内容: 执行的操作, 修改后的完整页面和提交之后的文件头. 打开文件时创建根节点的组没有逻辑记录.
+pub(crate) struct CommittedGroup
+{
+    pub(crate) operation: Option<Operation>,
+    pub(crate) pages: Vec<(PageId, Vec<u8>)>,
+    pub(crate) header: Header
+}
+
+/// PagedBtree 的预写日志.
+///
+/// 每个 insert 或 remove 是一组记录: 先是记录操作本身的逻辑记录, 然后是这次操作修改过的每一页的完整内容,
+/// 最后是带有文件头和整组校验和的提交记录. 恢复时把页面写回数据文件, 并用逻辑记录检查提交的文件头.
+/// 一组记录在内存中拼好后一次写入并 sync, 恢复时只重放校验和正确的完整组, 写了一半的组被丢弃, 所以每次操作要么完整生效要么完全没有发生.
+pub(crate) struct Wal<F>
+{
+    file: F,
+    len: u64,
+    group: Vec<u8>
+}
+
+impl<F: Storage> Wal<F>
+{
+    pub(crate) fn new(file: F) -> Self
+    {
+        Self{ file, len: 0, group: Vec::new() }
+    }
+
+    /// 日志当前的字节数
+    pub(crate) fn len(&self) -> u64
+    {
+        self.len
+    }
+
+    /// entry 是键和值依次编码的结果
+    pub(crate) fn log_insert(&mut self, entry: &[u8])
+    {
+        self.group.push(RECORD_INSERT);
+        entry.to_vec().encode(&mut self.group);
+    }
+
+    pub(crate) fn log_remove(&mut self, key: &[u8])
+    {
+        self.group.push(RECORD_REMOVE);
+        key.to_vec().encode(&mut self.group);
+    }
+
+    pub(crate) fn log_page(&mut self, id: PageId, page: &[u8])
+    {
+        debug_assert_eq!(page.len(), PAGE_SIZE);
+        self.group.push(RECORD_PAGE);
+        id.encode(&mut self.group);
+        self.group.extend_from_slice(page);
+    }
+
+    /// 写入提交记录并 sync. 返回之后这次操作在崩溃后也能恢复.
+    pub(crate) fn commit(&mut self, header: Header) -> io::Result<()>
+    {
+        self.group.push(RECORD_COMMIT);
+        header.encode(&mut self.group);
+        checksum(&self.group).encode(&mut self.group);
+
+        let group = take(&mut self.group);
+        self.file.seek(SeekFrom::Start(self.len))?;
+        self.file.write_all(&group)?;
+        self.file.sync()?;
+        self.len += group.len() as u64;
+        Ok(())
+    }
+
+    /// 丢弃还没有提交的记录
+    pub(crate) fn discard(&mut self)
+    {
+        self.group.clear();
+    }
+
+    /// 检查点之后日志中的内容都已经写入数据文件, 清空日志
+    pub(crate) fn truncate(&mut self) -> io::Result<()>
+    {
+        self.file.set_len(0)?;
+        self.file.sync()?;
+        self.len = 0;
+        Ok(())
+    }
+
+    /// 读出日志中所有完整提交的组, 末尾不完整或者校验和错误的部分被忽略
+    pub(crate) fn committed_groups(&mut self) -> io::Result<Vec<CommittedGroup>>
+    {
+        let mut bytes = Vec::new();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_end(&mut bytes)?;
+
+        let mut groups = Vec::new();
+        let mut input = &bytes[..];
+        while let Some(group) = parse_group(&mut input) {
+            groups.push(group);
+        }
+        Ok(groups)
+    }
+}
+
+fn parse_group(input: &mut &[u8]) -> Option<CommittedGroup>
+{
+    let start = *input;
+    let (mut operation, mut pages) = (None, Vec::new());
+    loop {
+        match take_bytes(input, 1)?[0]
+        {
+            // 逻辑记录只能出现在组的开头
+            RECORD_INSERT if start.len() - input.len() == 1 => operation = Some(Operation::Insert(Vec::decode(input)?)),
+            RECORD_REMOVE if start.len() - input.len() == 1 => operation = Some(Operation::Remove(Vec::decode(input)?)),
+            RECORD_PAGE => pages.push((PageId::decode(input)?, take_bytes(input, PAGE_SIZE)?.to_vec())),
+            RECORD_COMMIT => {
+                let header = Header::decode(input)?;
+                let covered = start.len() - input.len();
+                if u32::decode(input)? != checksum(&start[..covered]) { return None }
+                return Some(CommittedGroup{ operation, pages, header });
+            }
+            _ => return None
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use naive_btree::*;
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+// Miri 很慢, 在 Miri 下只做少量操作
+const OPS: u32 = if cfg!(miri) { 300 } else { 5000 };
+
+thread_local!(static SEED: Cell<u64> = const { Cell::new(0x2545_f491_4f6c_dd1d) });
+// 当前线程中存活的 Key 的数量, 每个测试在自己的线程中运行
+thread_local!(static LIVE: Cell<usize> = const { Cell::new(0) });
+
+fn random() -> u64
+{
+    SEED.with(|seed| {
+        let mut x = seed.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        seed.set(x);
+        x
+    })
+}
+
+fn live() -> usize
+{
+    LIVE.with(Cell::get)
+}
+
+/// 比较结果由 mode 决定的键, 析构时减少存活计数
+#[derive(Debug)]
+struct Key
+{
+    id: u32,
+    mode: Mode
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+enum Mode
+{
+    /// 每次比较返回随机结果
+    Random,
+    /// 按 id % 3 比较, 不满足传递性: 0 < 1 < 2 < 0
+    Cyclic,
+    /// 和 Eq 不一致: 所有键都相等, 但 Eq 只在 id 相同时成立
+    AllEqual
+}
+
+impl Key
+{
+    fn new(id: u32, mode: Mode) -> Self
+    {
+        LIVE.with(|live| live.set(live.get() + 1));
+        Self{ id, mode }
+    }
+}
+
+impl Clone for Key
+{
+    fn clone(&self) -> Self {
+        Self::new(self.id, self.mode)
+    }
+}
+
+impl Drop for Key
+{
+    fn drop(&mut self) {
+        LIVE.with(|live| live.set(live.get() - 1));
+    }
+}
+
+#[cfg(feature = "std")]
+impl Codec for Key
+{
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.id.encode(out);
+        (self.mode as u8).encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let id = u32::decode(input)?;
+        let mode = *MODES.get(u8::decode(input)? as usize)?;
+        Some(Self::new(id, mode))
+    }
+}
+
+impl PartialEq for Key
+{
+    fn eq(&self, other: &Self) -> bool { self.id == other.id }
+}
+
+impl Eq for Key {}
+
+impl PartialOrd for Key
+{
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+impl Ord for Key
+{
+    fn cmp(&self, other: &Self) -> Ordering {
+        match self.mode
+        {
+            Mode::Random => [Ordering::Less, Ordering::Equal, Ordering::Greater][random() as usize % 3],
+            Mode::Cyclic => match (self.id % 3 + 3 - other.id % 3) % 3 {
+                0 => Ordering::Equal,
+                1 => Ordering::Greater,
+                _ => Ordering::Less
+            },
+            Mode::AllEqual => Ordering::Equal
+        }
+    }
+}
+
+const MODES: [Mode; 3] = [Mode::Random, Mode::Cyclic, Mode::AllEqual];
+
+/// 对树做随机的操作. 结果不确定, 可以 panic, 但不能有未定义行为
+fn exercise(mode: Mode, mut op: impl FnMut(u32, Key, u64))
+{
+    let _ = catch_unwind(AssertUnwindSafe(|| {
+        for i in 0..OPS {
+            op(i, Key::new(i, mode), random());
+        }
+    }));
+}
+
+#[test]
+fn btree_survives_unlawful_ord()
+{
+    for mode in MODES {
+        let mut btree = Btree::new();
+        let mut snapshots = Vec::new();
+        exercise(mode, |i, key, r| {
+            match r % 8
+            {
+                0 | 1 => { btree.remove(&key); }
+                2 => { btree.get(&key); }
+                3 => { btree.range(&key..).take(5).count(); }
+                4 => { btree.iter_mut().for_each(|(_, v)| *v += 1); }
+                5 if i % 64 == 0 => { snapshots.push(btree.snapshot()); }
+                _ => { btree.insert(key, i); }
+            }
+        });
+        assert!(btree.iter().count() < OPS as usize);
+        drop(snapshots);
+        drop(btree);
+        // 所有键都只被析构了一次
+        assert_eq!(live(), 0, "{mode:?}");
+    }
+}
+
+#[test]
+fn variants_survive_unlawful_ord()
+{
+    for mode in MODES {
+        let mut arena = ArenaBtree::new();
+        let mut bplus = BplusTree::new();
+        let mut persistent = PersistentBtree::new();
+        exercise(mode, |i, key, r| {
+            match r % 4
+            {
+                0 => {
+                    arena.remove(&key);
+                    bplus.remove(&key);
+                    persistent.remove_in_place(&key);
+                }
+                1 => {
+                    arena.iter_mut().for_each(|(_, v)| *v += 1);
+                    bplus.range(&key..).count();
+                    persistent.get(&key);
+                }
+                _ => {
+                    arena.insert(key.clone(), i);
+                    bplus.insert(key.clone(), i);
+                    persistent.insert_in_place(key, i);
+                }
+            }
+        });
+        assert!(arena.iter().count() < OPS as usize);
+        assert!(bplus.iter().count() < OPS as usize);
+        assert!(persistent.iter().count() < OPS as usize);
+        drop((arena, bplus, persistent));
+        assert_eq!(live(), 0, "{mode:?}");
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn concurrent_and_paged_survive_unlawful_ord()
+{
+    for mode in MODES {
+        let concurrent = ConcurrentBtree::new();
+        // 缓冲池很小, 节点会被频繁换出再解码
+        let mut paged = PagedBtree::with_storage(std::io::Cursor::new(Vec::new()), std::io::Cursor::new(Vec::new()), 4).unwrap();
+        exercise(mode, |i, key, r| {
+            match r % 4
+            {
+                0 => {
+                    concurrent.remove(&key);
+                    paged.remove(&key).unwrap();
+                }
+                1 => {
+                    concurrent.get(&key);
+                    paged.get(&key).unwrap();
+                }
+                _ => {
+                    concurrent.insert(key.clone(), i);
+                    paged.insert(key, i).unwrap();
+                }
+            }
+        });
+        assert!(concurrent.len() < OPS as usize);
+        assert!(paged.iter().count() < OPS as usize);
+        drop((concurrent, paged));
+        assert_eq!(live(), 0, "{mode:?}");
+    }
+}
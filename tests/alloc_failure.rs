@@ -0,0 +1,114 @@
+use naive_btree::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+
+/// 本线程还允许成功的分配次数, None 表示不限制
+struct FailingAlloc;
+
+thread_local! {
+    static BUDGET: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+unsafe impl GlobalAlloc for FailingAlloc
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let allowed = BUDGET.try_with(|budget| match budget.get()
+        {
+            Some(0) => false,
+            Some(n) => { budget.set(Some(n - 1)); true }
+            None => true
+        });
+        if allowed.unwrap_or(true) { unsafe { System.alloc(layout) } } else { std::ptr::null_mut() }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: FailingAlloc = FailingAlloc;
+
+/// 在只允许 budget 次分配的情况下执行 f
+fn with_budget<T>(budget: usize, f: impl FnOnce() -> T) -> T
+{
+    BUDGET.with(|b| b.set(Some(budget)));
+    let result = f();
+    BUDGET.with(|b| b.set(None));
+    result
+}
+
+#[test]
+fn try_insert_leaves_tree_unchanged_on_failure()
+{
+    assert_eq!(with_budget(0, Btree::<u32, u32>::try_new).map(|_| ()), Err(AllocError));
+
+    let mut btree = Btree::new();
+    let mut std_map = BTreeMap::new();
+    let mut snapshot = None;
+    for i in 0..300u32 {
+        let key = i.wrapping_mul(2654435761) % 1000;
+        // 中途创建快照, 之后的插入还需要复制被共享的节点
+        if i == 150 { snapshot = Some((btree.snapshot(), std_map.clone())); }
+        let mut budget = 0;
+        loop {
+            match with_budget(budget, || btree.try_insert(key, i))
+            {
+                Ok(old) => { assert_eq!(old, std_map.insert(key, i)); break }
+                Err(AllocError) => assert!(btree.iter().map(|(k, v)| (*k, *v)).eq(std_map.clone()), "{i} {budget}")
+            }
+            budget += 1;
+        }
+        let stats = btree.stats();
+        assert_eq!(stats.entries, std_map.len());
+    }
+    let (snapshot, expected) = snapshot.unwrap();
+    assert!(snapshot.iter().map(|(k, v)| (*k, *v)).eq(expected));
+}
+
+#[test]
+fn try_remove_and_try_get_mut_leave_tree_unchanged_on_failure()
+{
+    let mut btree = Btree::new();
+    let mut std_map = BTreeMap::new();
+    for i in 0..300u32 {
+        btree.insert(i, i);
+        std_map.insert(i, i);
+    }
+    let mut snapshots = Vec::new();
+    for i in 0..300u32 {
+        let key = i.wrapping_mul(2654435761) % 300;
+        // 每次都有新的快照, 删除需要复制路径和兄弟节点
+        snapshots.push((btree.snapshot(), std_map.clone()));
+        let mut budget = 0;
+        loop {
+            match with_budget(budget, || btree.try_remove(&key))
+            {
+                Ok(removed) => { assert_eq!(removed, std_map.remove_entry(&key)); break }
+                Err(AllocError) => assert!(btree.iter().map(|(k, v)| (*k, *v)).eq(std_map.clone()), "{i} {budget}")
+            }
+            budget += 1;
+        }
+
+        let key = (key + 1) % 300;
+        snapshots.push((btree.snapshot(), std_map.clone()));
+        let mut budget = 0;
+        loop {
+            match with_budget(budget, || btree.try_get_mut(&key).map(|value| *value += 1000))
+            {
+                Ok(()) => { *std_map.get_mut(&key).unwrap() += 1000; break }
+                Err(Error::KeyNotFound) => { assert!(!std_map.contains_key(&key)); break }
+                Err(err) => {
+                    assert_eq!(err, Error::Alloc(AllocError));
+                    assert!(btree.iter().map(|(k, v)| (*k, *v)).eq(std_map.clone()), "{i} {budget}");
+                }
+            }
+            budget += 1;
+        }
+    }
+    assert!(btree.iter().map(|(k, v)| (*k, *v)).eq(std_map));
+    for (snapshot, expected) in snapshots {
+        assert!(snapshot.iter().map(|(k, v)| (*k, *v)).eq(expected));
+    }
+}
@@ -1,4 +1,5 @@
 use naive_btree::*;
+use std::ops::{Bound, RangeBounds};
 
 const DATA :[(i32,i32); 24] = [(1, 8), (4, 9), (6, 2), (8, 10), (11, 11), (13, 3), (14, 12), (16, 13),
                                 (17, 1), (19, 14), (22, 15), (23, 4), (27, 16), (34, 17), (35, 5), 
@@ -14,6 +15,74 @@ DATA.iter().for_each(|(a,b)| {btree.insert(*a, *b);} );
 btree
 }
 
+thread_local!(static SEED: std::cell::Cell<u64> = const { std::cell::Cell::new(0x9e37_79b9_7f4a_7c15) });
+
+/// xorshift 伪随机数. 每个测试在自己的线程中运行, 所以每次运行得到的序列都一样
+fn random() -> u64
+{
+    SEED.with(|seed| {
+        let mut x = seed.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        seed.set(x);
+        x
+    })
+}
+
+/// 可以和 BTreeMap 对比的树, 返回值和 BTreeMap 的同名方法相同
+trait Model<V>
+{
+    fn insert(&mut self, key: i32, value: V) -> Option<V>;
+    fn remove(&mut self, key: &i32) -> Option<(i32, V)>;
+}
+
+macro_rules! model {
+    ($($tree:ty),*) => {$(
+        impl<V: Clone> Model<V> for $tree
+        {
+            fn insert(&mut self, key: i32, value: V) -> Option<V> { <$tree>::insert(self, key, value) }
+            fn remove(&mut self, key: &i32) -> Option<(i32, V)> { <$tree>::remove(self, key) }
+        }
+    )*};
+}
+
+model!(Btree<i32, V>, ArenaBtree<i32, V>, BplusTree<i32, V>, ConcurrentBtree<i32, V>);
+
+impl<V: Clone> Model<V> for PersistentBtree<i32, V>
+{
+    fn insert(&mut self, key: i32, value: V) -> Option<V> { self.insert_in_place(key, value) }
+    fn remove(&mut self, key: &i32) -> Option<(i32, V)> { self.remove_in_place(key) }
+}
+
+impl<V: Codec, F: paged::Storage> Model<V> for PagedBtree<i32, V, F>
+{
+    fn insert(&mut self, key: i32, value: V) -> Option<V> { PagedBtree::insert(self, key, value).unwrap() }
+    fn remove(&mut self, key: &i32) -> Option<(i32, V)> { PagedBtree::remove(self, key).unwrap() }
+}
+
+/// 对 tree 和 BTreeMap 做同样的 20000 次随机插入和删除, 每次的返回值都必须相同.
+/// 键在 0..500 中, 大约三分之一的操作是删除, 插入的值由 value 根据这一步的随机数生成.
+/// 每一步之后用这一步的随机数调用 after 做额外的检查, 最后返回 BTreeMap.
+fn check_against_std<V, T>(tree: &mut T, value: impl Fn(u64) -> V, mut after: impl FnMut(&mut T, &std::collections::BTreeMap<i32, V>, u64))
+    -> std::collections::BTreeMap<i32, V>
+where V: Clone + PartialEq + std::fmt::Debug, T: Model<V>
+{
+    let mut expected = std::collections::BTreeMap::new();
+    for _ in 0..20000 {
+        let state = random();
+        let key = (state % 500) as i32;
+        if state.is_multiple_of(3) {
+            assert_eq!(tree.remove(&key), expected.remove(&key).map(|v| (key, v)));
+        }
+        else {
+            assert_eq!(tree.insert(key, value(state)), expected.insert(key, value(state)));
+        }
+        after(tree, &expected, state);
+    }
+    expected
+}
+
 #[test]
 fn read_works()
 {
@@ -110,4 +179,838 @@ fn index_nonexist()
 {
     let mut btree = init_test();
     btree[20] = 5;
-}
\ No newline at end of file
+}
+
+#[test]
+fn dump_works()
+{
+    let mut btree = Btree::new();
+    DATA[..6].iter().for_each(|(a,b)| {btree.insert(*a, *b);} );
+
+    assert_eq!(btree.dump_ascii(), "L0: [6: 2]\nL1: [1: 8, 4: 9] [8: 10, 11: 11, 13: 3]\n");
+
+    let dot = btree.to_dot();
+    assert!(dot.starts_with("digraph Btree {"));
+    assert!(dot.contains("n0:f0 -> n1;"));
+    assert!(dot.contains("n0:f1 -> n2;"));
+
+    let empty: Btree<i32, i32> = Btree::new();
+    assert_eq!(empty.dump_ascii(), "L0: []\n");
+}
+
+#[test]
+fn stats_works()
+{
+    let mut btree = init_test();
+    let stats = btree.stats();
+
+    assert_eq!(stats.entries, DATA.len());
+    assert_eq!(stats.height, stats.nodes_per_level.len());
+    assert_eq!(stats.nodes_per_level[0], 1);
+    assert_eq!(stats.leaf_nodes + stats.internal_nodes, stats.nodes_per_level.iter().sum::<usize>());
+    assert_eq!(stats.leaf_nodes, *stats.nodes_per_level.last().unwrap());
+    assert!(stats.splits > 0);
+    assert!(stats.average_occupancy > 0.0 && stats.average_occupancy <= 1.0);
+    assert!(stats.leaf_bytes + stats.internal_bytes >= DATA.len() * size_of::<(i32,i32)>());
+    println!("{:?}", stats);
+
+    DATA.iter().for_each(|(key,_)| { btree.remove(key); });
+    let stats = btree.stats();
+    assert_eq!(stats.entries, 0);
+    assert_eq!(stats.height, 1);
+    assert!(stats.merges > 0);
+    assert!(stats.borrows > 0);
+}
+
+#[test]
+fn random_ops_match_std()
+{
+    let mut btree = Btree::new();
+    let expected = check_against_std(&mut btree, |state| state, |btree, expected, state| {
+        if state.is_multiple_of(101) {
+            let (a, b) = ((state % 500) as i32, (state % 600) as i32);
+            let (a, b) = (a.min(b), a.max(b));
+            assert!(btree.range(a..=b).eq(expected.range(a..=b)));
+        }
+    });
+
+    assert!(btree.iter().eq(expected.iter()));
+    expected.keys().for_each(|key| assert_eq!(btree.get(key), expected.get(key)));
+}
+
+#[test]
+fn arena_works()
+{
+    let mut arena = ArenaBtree::new();
+    DATA.iter().for_each(|(a,b)| {arena.insert(*a, *b);} );
+
+    DATA.iter().for_each(|&(key,value)| assert_eq!(value, arena[key]));
+    assert!(arena.iter().map(|(k,v)| (*k,*v)).eq(DATA));
+
+    let snapshot = arena.clone();
+    arena.iter_mut().for_each(|(_,v)| *v = -(*v));
+    DATA.iter().for_each(|&(key,value)| {
+        assert_eq!(arena.get(&key), Some(&-value));
+        assert_eq!(snapshot.get(&key), Some(&value));
+    });
+
+    for (key, value) in DATA {
+        assert_eq!(arena.remove(&key), Some((key, -value)));
+        assert!(arena.get(&key).is_none(), "移除值之后依然能找到");
+    }
+    assert_eq!(arena.iter().next(), None);
+
+    let mut arena = snapshot;
+    arena.clear();
+    assert_eq!(arena.iter().next(), None);
+    arena.insert(1, 1);
+    assert_eq!(arena[1], 1);
+}
+
+#[test]
+fn arena_random_ops_match_std()
+{
+    let mut arena = ArenaBtree::new();
+    let expected = check_against_std(&mut arena, |state| state, |_, _, _| {});
+    assert!(arena.iter().eq(expected.iter()));
+}
+
+#[test]
+fn range_works()
+{
+    let btree = init_test();
+
+    assert!(btree.range(8..17).map(|(k,_)| *k).eq([8, 11, 13, 14, 16]));
+    assert!(btree.range(8..=17).map(|(k,_)| *k).eq([8, 11, 13, 14, 16, 17]));
+    assert!(btree.range(9..).map(|(k,_)| *k).eq(DATA[4..].iter().map(|(k,_)| *k)));
+    assert!(btree.range(..=4).map(|(k,_)| *k).eq([1, 4]));
+    assert!(btree.range(..).map(|(k,v)| (*k,*v)).eq(DATA));
+    assert_eq!(btree.range(80..).next(), None);
+    assert_eq!(btree.range(20..20).next(), None);
+    assert_eq!(btree.range((Bound::Included(30), Bound::Excluded(10))).next(), None);
+}
+
+#[test]
+fn bplus_works()
+{
+    let mut bplus = BplusTree::new();
+    DATA.iter().for_each(|(a,b)| {bplus.insert(*a, *b);} );
+
+    DATA.iter().for_each(|&(key,value)| assert_eq!(value, bplus[key]));
+    assert!(bplus.iter().map(|(k,v)| (*k,*v)).eq(DATA));
+    assert!(bplus.iter().rev().map(|(k,v)| (*k,*v)).eq(DATA.into_iter().rev()));
+    assert!(bplus.range(8..=17).map(|(k,_)| *k).eq([8, 11, 13, 14, 16, 17]));
+    assert!(bplus.range(..4).rev().map(|(k,_)| *k).eq([1]));
+    assert_eq!(bplus.range((Bound::Included(30), Bound::Excluded(10))).next(), None);
+
+    bplus.iter_mut().for_each(|(_,v)| *v = -(*v));
+    DATA.iter().rev().for_each(|(key,value)| {
+        assert_eq!(bplus.remove(key), Some((*key, -*value)));
+        assert!(bplus.get(key).is_none(), "移除值之后依然能找到");
+    });
+    assert_eq!(bplus.iter().next(), None);
+    assert_eq!(bplus.stats().height, 1);
+}
+
+#[test]
+fn bplus_random_ops_match_std()
+{
+    let mut bplus = BplusTree::new();
+    let expected = check_against_std(&mut bplus, |state| state, |bplus, expected, state| {
+        if state.is_multiple_of(101) {
+            let (a, b) = ((state % 500) as i32, (state % 600) as i32);
+            let (a, b) = (a.min(b), a.max(b));
+            assert!(bplus.range(a..b).eq(expected.range(a..b)));
+            assert!(bplus.range(..=b).rev().eq(expected.range(..=b).rev()));
+        }
+    });
+
+    assert!(bplus.iter().eq(expected.iter()));
+    assert!(bplus.iter().rev().eq(expected.iter().rev()));
+}
+
+#[test]
+fn persistent_works()
+{
+    let mut versions = vec![PersistentBtree::new()];
+    for (key, value) in DATA {
+        let next = versions.last().unwrap().insert(key, value);
+        versions.push(next);
+    }
+
+    for (n, version) in versions.iter().enumerate() {
+        assert_eq!(version.len(), n);
+        assert!(version.iter().map(|(k,v)| (*k,*v)).eq(DATA[..n].iter().copied()));
+    }
+
+    let full = versions.last().unwrap().clone();
+    assert!(full.remove(&5).ptr_eq(&full));
+
+    let snapshot = full.snapshot();
+    let reader = std::thread::spawn(move || DATA.iter().all(|&(key,value)| snapshot[key] == value));
+
+    let mut emptied = full.clone();
+    for (key, _) in DATA {
+        emptied = emptied.remove(&key);
+        assert!(!emptied.contains_key(&key), "移除值之后依然能找到");
+    }
+    assert!(emptied.is_empty());
+    assert_eq!(emptied.iter().next(), None);
+    assert!(reader.join().unwrap());
+    assert_eq!(full.len(), DATA.len());
+}
+
+#[test]
+fn persistent_random_ops_match_std()
+{
+    let mut tree = PersistentBtree::new();
+    let mut history = Vec::new();
+    let mut step = 0;
+    let expected = check_against_std(&mut tree, |state| state, |tree, expected, _| {
+        if step % 1000 == 0 {
+            history.push((tree.snapshot(), expected.clone()));
+        }
+        step += 1;
+    });
+
+    assert!(tree.iter().eq(expected.iter()));
+    for (snapshot, expected) in history {
+        assert_eq!(snapshot.len(), expected.len());
+        assert!(snapshot.iter().eq(expected.iter()));
+    }
+}
+
+#[test]
+fn snapshot_works()
+{
+    let mut btree = Btree::new();
+    DATA.iter().for_each(|(a,b)| {btree.insert(*a, *b);} );
+
+    let snapshot = btree.snapshot();
+    let reader = {
+        let snapshot = snapshot.clone();
+        std::thread::spawn(move || DATA.iter().all(|&(key,value)| snapshot[key] == value))
+    };
+
+    for (key, _) in DATA {
+        btree.remove(&key);
+    }
+    btree.insert(100, 100);
+    for (_, value) in btree.iter_mut() {
+        *value += 1;
+    }
+
+    assert!(snapshot.iter().map(|(k,v)| (*k,*v)).eq(DATA.iter().copied()));
+    assert_eq!(snapshot.get(&100), None);
+    assert_eq!(btree[100], 101);
+    assert!(reader.join().unwrap());
+}
+
+#[test]
+fn snapshot_random_ops_match_std()
+{
+    let mut tree = Btree::new();
+    let mut history = Vec::new();
+    let mut step = 0;
+    let expected = check_against_std(&mut tree, |state| state.to_string(), |tree, expected, _| {
+        if step % 1000 == 0 {
+            history.push((tree.snapshot(), expected.clone()));
+        }
+        // 丢掉一部分快照, 让树和快照交替成为节点的最后一个持有者
+        if step % 3000 == 0 {
+            history.retain(|_| random().is_multiple_of(2));
+        }
+        step += 1;
+    });
+
+    assert!(tree.iter().eq(expected.iter()));
+    for (snapshot, expected) in history {
+        assert!(snapshot.iter().eq(expected.iter()));
+    }
+}
+
+#[test]
+fn snapshots_dropped_on_other_threads_are_freed()
+{
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // 存活的值的数量, 泄漏的节点中的值不会被析构
+    static LIVE: AtomicUsize = AtomicUsize::new(0);
+    struct Tracked;
+    impl Tracked
+    {
+        fn new() -> Self { LIVE.fetch_add(1, Ordering::Relaxed); Tracked }
+    }
+    impl Clone for Tracked
+    {
+        fn clone(&self) -> Self { Self::new() }
+    }
+    impl Drop for Tracked
+    {
+        fn drop(&mut self) { LIVE.fetch_sub(1, Ordering::Relaxed); }
+    }
+
+    // 另一个线程在树复制被共享的节点的同时释放快照, 快照和树都可能是节点的最后一个持有者
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let dropper = std::thread::spawn(move || receiver.into_iter().for_each(drop));
+    let mut tree = Btree::new();
+    for step in 0..20000u64 {
+        tree.insert(step % 500, Tracked::new());
+        if step % 3 == 0 {
+            tree.remove(&(step * 7 % 500));
+        }
+        sender.send(tree.snapshot()).unwrap();
+    }
+    drop(sender);
+    dropper.join().unwrap();
+    drop(tree);
+    assert_eq!(LIVE.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn concurrent_random_ops_match_std()
+{
+    let mut tree = ConcurrentBtree::new();
+    let expected = check_against_std(&mut tree, |state| state, |tree, expected, _| assert_eq!(tree.len(), expected.len()));
+
+    for key in 0..500 {
+        assert_eq!(tree.get(&key), expected.get(&key).copied());
+    }
+}
+
+#[test]
+fn concurrent_threads_work()
+{
+    let tree = ConcurrentBtree::new();
+    std::thread::scope(|scope| {
+        for t in 0..4 {
+            let tree = &tree;
+            scope.spawn(move || {
+                // 每个线程写自己的键, 同时读取其他线程的键
+                for i in 0..2000 {
+                    let key = i * 4 + t;
+                    assert_eq!(tree.insert(key, key * 10), None);
+                    tree.get(&(key ^ 1));
+                    if i % 2 == 1 {
+                        assert_eq!(tree.remove(&(key - 4)), Some((key - 4, (key - 4) * 10)));
+                    }
+                }
+            });
+        }
+    });
+
+    assert_eq!(tree.len(), 4 * 1000);
+    for key in 0..8000 {
+        assert_eq!(tree.contains_key(&key), (key / 4) % 2 == 1, "{key}");
+    }
+}
+
+#[test]
+fn paged_random_ops_match_std()
+{
+    use std::io::Cursor;
+
+    // 缓冲池只有 4 个节点, 大部分操作都要读写磁盘页
+    let mut tree = PagedBtree::with_storage(Cursor::new(Vec::new()), Cursor::new(Vec::new()), 4).unwrap();
+    let expected = check_against_std(&mut tree, |state| state.to_string(), |_, _, _| {});
+
+    assert_eq!(tree.len(), expected.len());
+    assert!(tree.iter().map(Result::unwrap).eq(expected.into_iter()));
+}
+
+#[test]
+fn paged_reopen_works()
+{
+    let path = std::env::temp_dir().join(format!("naive_btree_paged_{}.db", std::process::id()));
+    let wal_path = path.with_extension("db-wal");
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&wal_path);
+    {
+        let mut tree = PagedBtree::open(&path).unwrap();
+        for (key, value) in DATA {
+            tree.insert(key, value.to_string()).unwrap();
+        }
+        assert_eq!(tree.remove(&DATA[0].0).unwrap(), Some((DATA[0].0, DATA[0].1.to_string())));
+    }
+
+    let mut tree = PagedBtree::<i32, String>::open_with_capacity(&path, 1).unwrap();
+    assert_eq!(tree.len(), DATA.len() - 1);
+    for (key, value) in &DATA[1..] {
+        assert_eq!(tree.get(key).unwrap(), Some(value.to_string()));
+    }
+    assert_eq!(tree.get(&DATA[0].0).unwrap(), None);
+
+    // 超过单页大小的成员会被拒绝
+    assert!(tree.insert(0, "x".repeat(4096)).is_err());
+    tree.checkpoint().unwrap();
+    drop(tree);
+    assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), 0);
+
+    std::fs::write(&path, "not a tree".repeat(1000)).unwrap();
+    assert!(PagedBtree::<i32, String>::open(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&wal_path).unwrap();
+}
+
+/// 内存中的"磁盘", 写入和 sync 的次数用完之后模拟进程被杀死:
+/// 最后一次写入只写一半, 之后所有写入都失败
+struct FaultyFile
+{
+    disk: std::rc::Rc<std::cell::RefCell<Vec<u8>>>,
+    pos: u64,
+    budget: std::rc::Rc<std::cell::Cell<usize>>
+}
+
+impl FaultyFile
+{
+    fn spend(&self) -> std::io::Result<bool>
+    {
+        match self.budget.get() {
+            0 => Err(std::io::Error::other("injected crash")),
+            n => { self.budget.set(n - 1); Ok(n == 1) }
+        }
+    }
+}
+
+impl std::io::Read for FaultyFile
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize>
+    {
+        let disk = self.disk.borrow();
+        let start = (self.pos as usize).min(disk.len());
+        let n = buf.len().min(disk.len() - start);
+        buf[..n].copy_from_slice(&disk[start..start + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl std::io::Write for FaultyFile
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize>
+    {
+        let last = self.spend()?;
+        let buf = if last { &buf[..buf.len() / 2] } else { buf };
+        let mut disk = self.disk.borrow_mut();
+        let end = self.pos as usize + buf.len();
+        if disk.len() < end { disk.resize(end, 0); }
+        disk[self.pos as usize..end].copy_from_slice(buf);
+        self.pos = end as u64;
+        if last { return Err(std::io::Error::other("injected crash")) }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()>
+    {
+        Ok(())
+    }
+}
+
+impl std::io::Seek for FaultyFile
+{
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64>
+    {
+        self.pos = match pos {
+            std::io::SeekFrom::Start(n) => n,
+            std::io::SeekFrom::End(n) => (self.disk.borrow().len() as i64 + n) as u64,
+            std::io::SeekFrom::Current(n) => (self.pos as i64 + n) as u64
+        };
+        Ok(self.pos)
+    }
+}
+
+impl Storage for FaultyFile
+{
+    fn sync(&mut self) -> std::io::Result<()>
+    {
+        self.spend().map(|_| ())
+    }
+
+    fn set_len(&mut self, len: u64) -> std::io::Result<()>
+    {
+        self.spend()?;
+        self.disk.borrow_mut().resize(len as usize, 0);
+        Ok(())
+    }
+}
+
+#[test]
+fn paged_recovers_from_crash_at_every_write()
+{
+    use std::cell::{Cell, RefCell};
+    use std::collections::BTreeMap;
+    use std::rc::Rc;
+
+    let disks = (Rc::new(RefCell::new(Vec::new())), Rc::new(RefCell::new(Vec::new())));
+    let open = |budget: usize| {
+        let budget = Rc::new(Cell::new(budget));
+        let file = |disk: &Rc<RefCell<Vec<u8>>>| FaultyFile{ disk: disk.clone(), pos: 0, budget: budget.clone() };
+        PagedBtree::<i32, i32, FaultyFile>::with_storage(file(&disks.0), file(&disks.1), 2)
+    };
+
+    for budget in 0.. {
+        disks.0.borrow_mut().clear();
+        disks.1.borrow_mut().clear();
+
+        // 依次执行操作直到写入次数用完, 记录最后一次成功的操作之后和失败的那次操作之后的内容
+        let mut expected = BTreeMap::new();
+        let mut committed = None;
+        let mut finished = false;
+        if let Ok(mut tree) = open(budget) {
+            committed = Some(expected.clone());
+            finished = (0..60).all(|step| {
+                let key = step * 7 % 23;
+                let ok = if step % 3 == 2 {
+                    expected.remove(&key);
+                    tree.remove(&key).is_ok()
+                }
+                else {
+                    expected.insert(key, step);
+                    tree.insert(key, step).is_ok()
+                };
+                let ok = ok && (step % 20 != 19 || tree.checkpoint().is_ok());
+                if ok { committed = Some(expected.clone()); }
+                ok
+            });
+        }
+
+        let mut tree = open(usize::MAX).unwrap();
+        let recovered: BTreeMap<_,_> = tree.iter().map(|r| r.unwrap_or_else(|e| panic!("{budget} {e}"))).collect();
+        assert_eq!(tree.len(), recovered.len());
+        match committed {
+            None => assert!(recovered.is_empty()),
+            Some(committed) => assert!(recovered == committed || recovered == expected, "写入 {budget} 次后崩溃, 恢复的内容不对")
+        }
+        if finished { break }
+    }
+}
+
+#[test]
+fn paged_recovery_checks_logged_operations()
+{
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    let disks = (Rc::new(RefCell::new(Vec::new())), Rc::new(RefCell::new(Vec::new())));
+    let file = |disk: &Rc<RefCell<Vec<u8>>>| FaultyFile{ disk: disk.clone(), pos: 0, budget: Rc::new(Cell::new(usize::MAX)) };
+    {
+        let mut tree = PagedBtree::<i32, String, FaultyFile>::with_storage(file(&disks.0), file(&disks.1), 2).unwrap();
+        for (key, value) in DATA {
+            tree.insert(key, value.to_string()).unwrap();
+        }
+        tree.remove(&DATA[0].0).unwrap();
+    }
+
+    // 日志中的插入记录不能按 (i32, u64) 解码, 恢复时拒绝打开
+    let disk_copies = (disks.0.borrow().clone(), disks.1.borrow().clone());
+    assert!(PagedBtree::<i32, u64, FaultyFile>::with_storage(file(&disks.0), file(&disks.1), 2).is_err());
+    assert!((disks.0.borrow().clone(), disks.1.borrow().clone()) == disk_copies);
+
+    let mut tree = PagedBtree::<i32, String, FaultyFile>::with_storage(file(&disks.0), file(&disks.1), 2).unwrap();
+    assert_eq!(tree.len(), DATA.len() - 1);
+    assert_eq!(tree.get(&DATA[1].0).unwrap(), Some(DATA[1].1.to_string()));
+}
+
+#[test]
+fn transaction_works()
+{
+    let mut btree = Btree::new();
+    DATA.iter().for_each(|(a,b)| {btree.insert(*a, *b);} );
+    let before: Vec<_> = btree.iter().map(|(k,v)| (*k,*v)).collect();
+
+    let mut tx = btree.transaction();
+    assert_eq!(tx.insert(DATA[0].0, 100), Some(&DATA[0].1));
+    assert_eq!(tx.insert(1000, 1000), None);
+    assert_eq!(tx.remove(&DATA[1].0), Some((&DATA[1].0, &DATA[1].1)));
+    assert_eq!(tx.remove(&-1), None);
+    (0..50).for_each(|key| { tx.remove(&key); });
+    assert_eq!(tx.get(&1000), Some(&1000));
+    tx.rollback();
+    assert!(btree.iter().map(|(k,v)| (*k,*v)).eq(before.iter().copied()));
+
+    // 提前返回和 panic 都会回滚
+    let result: Result<(), ()> = (|| {
+        let mut tx = btree.transaction();
+        tx.insert(2000, 2000);
+        Err(())?;
+        tx.commit();
+        Ok(())
+    })();
+    assert!(result.is_err());
+    assert_eq!(btree.get(&2000), None);
+
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let mut tx = btree.transaction();
+        tx.remove(&DATA[2].0);
+        panic!("校验失败");
+    }));
+    assert!(panicked.is_err());
+    assert!(btree.iter().map(|(k,v)| (*k,*v)).eq(before.iter().copied()));
+
+    let mut tx = btree.transaction();
+    tx.insert(3000, 3000);
+    tx.remove(&DATA[0].0);
+    tx.commit();
+    assert_eq!(btree.get(&3000), Some(&3000));
+    assert_eq!(btree.get(&DATA[0].0), None);
+}
+
+#[test]
+fn from_sorted_iter_works()
+{
+    for n in 0..120 {
+        let mut btree = Btree::from_sorted_iter((0..n).map(|key| (key, key * 2)));
+        assert!(btree.iter().map(|(k,v)| (*k,*v)).eq((0..n).map(|key| (key, key * 2))));
+        assert_eq!(btree.stats().entries, n as usize);
+
+        // 构建出的树必须满足最少成员数, 否则删除时会出错
+        btree.insert(n, 0);
+        for key in (0..n + 1).step_by(2).chain((1..n + 1).step_by(2).rev()) {
+            btree.remove(&key);
+        }
+        assert_eq!(btree.iter().next(), None);
+    }
+}
+
+#[test]
+#[should_panic]
+fn from_sorted_iter_rejects_unordered()
+{
+    Btree::from_sorted_iter([(2, 0), (1, 0)]);
+}
+
+#[test]
+fn dump_round_trip()
+{
+    let mut btree = Btree::new();
+    for key in 0..300u32 {
+        btree.insert(key.wrapping_mul(2654435761) % 1000, format!("v{key}"));
+    }
+    let mut bytes = Vec::new();
+    btree.write_to(&mut bytes).unwrap();
+
+    let loaded = Btree::<u32, String>::read_from(&bytes[..]).unwrap();
+    assert!(loaded.iter().eq(btree.iter()));
+    let mut empty = Vec::new();
+    Btree::<u32, String>::new().write_to(&mut empty).unwrap();
+    assert!(Btree::<u32, String>::read_from(&empty[..]).unwrap().iter().next().is_none());
+
+    // 每一种截断和每一个字节的损坏都必须被发现
+    for len in 0..bytes.len() {
+        assert!(matches!(Btree::<u32, String>::read_from(&bytes[..len]), Err(LoadError::Truncated)), "{len}");
+    }
+    for pos in 0..bytes.len() {
+        let mut corrupted = bytes.clone();
+        corrupted[pos] ^= 0x40;
+        assert!(Btree::<u32, String>::read_from(&corrupted[..]).is_err(), "{pos}");
+    }
+    bytes[8] = 9;
+    assert!(matches!(Btree::<u32, String>::read_from(&bytes[..]), Err(LoadError::UnsupportedVersion(9))));
+    bytes[..8].copy_from_slice(b"NOTADUMP");
+    assert!(matches!(Btree::<u32, String>::read_from(&bytes[..]), Err(LoadError::BadMagic)));
+}
+
+#[test]
+fn frozen_works()
+{
+    let mut btree = Btree::new();
+    let mut std_map = std::collections::BTreeMap::new();
+    for i in 0..500u32 {
+        let key = i.wrapping_mul(2654435761) % 2000 * 2;
+        btree.insert(key, format!("v{i}"));
+        std_map.insert(key, format!("v{i}"));
+    }
+    let frozen = FrozenBtree::from_btree(&btree);
+    assert_eq!(frozen.len(), std_map.len());
+    for key in 0..4001 {
+        assert_eq!(frozen.get(&key), std_map.get(&key).cloned());
+    }
+    assert!(frozen.iter().eq(std_map.clone()));
+    assert!(frozen.iter().rev().eq(std_map.clone().into_iter().rev()));
+    for (start, end) in [(0, 4000), (7, 7), (8, 8), (100, 50), (1001, 3000)] {
+        let expected = |range: (Bound<u32>, Bound<u32>)| std_map.iter().filter(|(k, _)| range.contains(*k)).map(|(k, v)| (*k, v.clone())).collect::<Vec<_>>();
+        for range in [(Bound::Included(start), Bound::Included(end)), (Bound::Excluded(start), Bound::Excluded(end)),
+                      (Bound::Unbounded, Bound::Included(end)), (Bound::Excluded(start), Bound::Unbounded)] {
+            assert_eq!(frozen.range(range).collect::<Vec<_>>(), expected(range));
+        }
+    }
+
+    // 字节可以原样借用使用
+    let borrowed = FrozenBtree::<u32, String, &[u8]>::from_bytes(frozen.as_bytes()).unwrap();
+    assert!(borrowed.iter().eq(std_map.clone()));
+    assert!(FrozenBtree::<u32, String>::from_btree(&Btree::new()).iter().next().is_none());
+    let bytes = frozen.as_bytes();
+    assert!(matches!(FrozenBtree::<u32, String, _>::from_bytes(&bytes[..bytes.len() - 1]), Err(LoadError::Truncated)));
+    assert!(matches!(FrozenBtree::<u32, String, _>::from_bytes(&bytes[1..]), Err(LoadError::BadMagic)));
+    // 数据长度接近 u64::MAX 的文件头不能让长度的计算溢出
+    let mut crafted = bytes[..24].to_vec();
+    crafted[16..24].copy_from_slice(&(u64::MAX - 8).to_le_bytes());
+    assert!(matches!(FrozenBtree::<u32, String, _>::from_bytes(crafted), Err(LoadError::Corrupted)));
+
+    #[cfg(feature = "mmap")]
+    {
+        let path = std::env::temp_dir().join(format!("naive_btree_frozen_{}", std::process::id()));
+        frozen.write_to(std::fs::File::create(&path).unwrap()).unwrap();
+        let mapped = FrozenBtree::<u32, String, _>::open(&path).unwrap();
+        assert!(mapped.iter().eq(std_map.clone()));
+        assert_eq!(mapped.get(&std_map.keys().next().copied().unwrap()), std_map.values().next().cloned());
+        drop(mapped);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
+
+/// 记录还没有释放的分配次数的分配器
+#[derive(Clone, Default)]
+struct CountingAlloc
+{
+    live: std::rc::Rc<std::cell::Cell<isize>>,
+    total: std::rc::Rc<std::cell::Cell<usize>>
+}
+
+unsafe impl allocator_api2::alloc::Allocator for CountingAlloc
+{
+    fn allocate(&self, layout: std::alloc::Layout) -> Result<std::ptr::NonNull<[u8]>, allocator_api2::alloc::AllocError> {
+        self.live.set(self.live.get() + 1);
+        self.total.set(self.total.get() + 1);
+        Global.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: std::ptr::NonNull<u8>, layout: std::alloc::Layout) {
+        self.live.set(self.live.get() - 1);
+        unsafe { Global.deallocate(ptr, layout) }
+    }
+}
+
+#[test]
+fn custom_allocator_works()
+{
+    let alloc = CountingAlloc::default();
+    let mut btree = Btree::new_in(alloc.clone());
+    let mut std_map = std::collections::BTreeMap::new();
+    let mut snapshots = Vec::new();
+    for i in 0..2000u32 {
+        let key = i.wrapping_mul(2654435761) % 300;
+        if i % 3 == 0 {
+            assert_eq!(btree.remove(&key), std_map.remove_entry(&key));
+        }
+        else {
+            assert_eq!(btree.try_insert(key, i), Ok(std_map.insert(key, i)));
+        }
+        if i % 500 == 0 { snapshots.push((btree.snapshot(), std_map.clone())); }
+    }
+    assert!(btree.iter().map(|(k, v)| (*k, *v)).eq(std_map.clone()));
+    drop(btree);
+    for (snapshot, expected) in snapshots {
+        assert!(snapshot.iter().map(|(k, v)| (*k, *v)).eq(expected));
+    }
+    // 所有节点都由 alloc 分配, 并且都已经还给了它
+    assert!(alloc.total.get() > 100);
+    assert_eq!(alloc.live.get(), 0);
+}
+
+#[test]
+fn try_accessors_work()
+{
+    let mut btree: Btree<i32, &str> = [(1, "a"), (2, "b")].into_iter().fold(Btree::new(), |mut tree, (k, v)| { tree.insert(k, v); tree });
+    assert_eq!(btree.try_get(&1), Ok(&"a"));
+    assert_eq!(btree.try_index(3), Err(Error::KeyNotFound));
+    *btree.try_index_mut(2).unwrap() = "c";
+    assert_eq!(btree.try_get_mut(&4).map(|_| ()), Err(Error::KeyNotFound));
+    assert_eq!(btree[2], "c");
+
+    assert_eq!(Btree::try_from_sorted_iter([(1, 0), (1, 0)]).map(|_| ()).unwrap_err(), Error::DuplicateKey);
+    assert_eq!(Btree::try_from_sorted_iter([(2, 0), (1, 0)]).map(|_| ()).unwrap_err(), Error::UnsortedKeys);
+    assert_eq!(Error::from(AllocError), Error::Alloc(AllocError));
+
+    // Index 不要求键实现 Debug, 需要在 panic 消息中看到键时用 expect
+    let message = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| btree[42])).unwrap_err();
+    assert!(!message.downcast_ref::<String>().unwrap().contains("42"));
+    let message = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| *btree.expect(&42))).unwrap_err();
+    assert!(message.downcast_ref::<String>().unwrap().contains("42"));
+    *btree.expect_mut(&1) = "d";
+    assert_eq!(btree.expect(&1), &"d");
+
+    #[derive(PartialEq, Eq, PartialOrd, Ord)]
+    struct NoDebug(i32);
+    let mut tree = Btree::new();
+    tree.insert(NoDebug(1), 1);
+    tree[NoDebug(1)] += 1;
+    assert_eq!(tree[NoDebug(1)], 2);
+}
+
+#[test]
+fn works_on_small_stack()
+{
+    // 查找、插入、删除和遍历都是循环, 很深的树也只占用固定的栈空间
+    std::thread::Builder::new().stack_size(32 * 1024).spawn(|| {
+        let mut btree = Btree::new();
+        for i in 0..100_000u32 {
+            btree.insert(i, i);
+        }
+        assert_eq!(btree.range(50_000..50_003).map(|(k, _)| *k).collect::<Vec<_>>(), [50_000, 50_001, 50_002]);
+        for i in (0..100_000u32).step_by(2) {
+            assert_eq!(btree.remove(&i), Some((i, i)));
+        }
+        assert_eq!(btree.get(&99_999), Some(&99_999));
+        assert_eq!(btree.iter().count(), 50_000);
+    }).unwrap().join().unwrap();
+}
+
+#[test]
+fn clear_works()
+{
+    let mut btree = init_test();
+    let snapshot = btree.snapshot();
+    btree.clear();
+    assert_eq!(btree.iter().count(), 0);
+    assert_eq!(btree.get(&1), None);
+    // 被快照共享的节点不受影响
+    assert!(snapshot.iter().map(|(k, v)| (*k, *v)).eq(DATA));
+
+    btree.insert(3, 3);
+    assert_eq!(btree.iter().collect::<Vec<_>>(), [(&3, &3)]);
+    drop(snapshot);
+    btree.clear();
+    assert_eq!(btree.iter().count(), 0);
+}
+
+#[test]
+fn interval_map_works()
+{
+    let mut intervals = IntervalMap::new();
+    let mut expected = std::collections::BTreeMap::new();
+    let brute_force = |expected: &std::collections::BTreeMap<i32, (i32, i32)>, lower: i32, upper: i32| expected.iter()
+        .filter(|(start, (end, _))| **start <= upper && *end >= lower)
+        .map(|(start, (end, v))| (*start, *end, *v))
+        .collect::<Vec<_>>();
+    for i in 0..3000 {
+        let state = random();
+        let start = (state % 1000) as i32;
+        // 前面以插入为主, 后面以删除为主, 删除时会发生借用和合并
+        let remove = if i < 2000 { i % 4 == 0 } else { i % 4 != 0 };
+        if remove {
+            assert_eq!(intervals.remove(&start), expected.remove(&start).map(|(end, v)| (start..=end, v)));
+        }
+        else {
+            // 大部分区间很短, 少数很长, 这样剪枝和不剪枝的子树都会出现
+            let end = start + if state.is_multiple_of(17) { (state % 500) as i32 } else { (state % 10) as i32 };
+            assert_eq!(intervals.insert(start..=end, i), expected.insert(start, (end, i)));
+        }
+        assert_eq!(intervals.len(), expected.len());
+        // 每次修改之后子树的最大终点都必须是准确的, 否则查询会漏掉区间
+        if i % 7 == 0 {
+            let point = (random() % 1100) as i32;
+            assert_eq!(intervals.containing(&point).map(|(s, e, v)| (*s, *e, *v)).collect::<Vec<_>>(), brute_force(&expected, point, point));
+        }
+    }
+    let brute_force = |lower, upper| brute_force(&expected, lower, upper);
+    for (lower, upper) in [(0, 0), (100, 200), (-5, 3), (990, 2000), (500, 500), (0, 1500)] {
+        assert_eq!(intervals.overlapping(lower..=upper).map(|(s, e, v)| (*s, *e, *v)).collect::<Vec<_>>(), brute_force(lower, upper));
+        assert_eq!(intervals.containing(&lower).map(|(s, e, v)| (*s, *e, *v)).collect::<Vec<_>>(), brute_force(lower, lower));
+    }
+    assert_eq!(intervals.overlapping(300..300).count(), 0);
+    assert_eq!(intervals.overlapping((Bound::Excluded(10), Bound::Excluded(20))).map(|(s, e, v)| (*s, *e, *v)).collect::<Vec<_>>(),
+               expected.iter().filter(|(start, (end, _))| **start < 20 && *end > 10).map(|(s, (e, v))| (*s, *e, *v)).collect::<Vec<_>>());
+    assert!(intervals.iter().map(|(s, _, _)| *s).eq(expected.keys().copied()));
+    assert_eq!(intervals.get(&-1), None);
+}
@@ -0,0 +1,69 @@
+//! 用 loom 穷举 ConcurrentBtree 加锁协议的线程交错, 运行方式:
+//! RUSTFLAGS="--cfg loom" cargo test --release --test loom_concurrent
+#![cfg(loom)]
+
+use loom::sync::Arc;
+use loom::thread;
+use naive_btree::ConcurrentBtree;
+
+/// 预先插入 [0, n), 让之后的操作发生在多层的树上
+fn filled(n: i32) -> Arc<ConcurrentBtree<i32, i32>>
+{
+    let tree = ConcurrentBtree::new();
+    for key in 0..n {
+        tree.insert(key, key);
+    }
+    Arc::new(tree)
+}
+
+#[test]
+fn insert_split_races_with_reader()
+{
+    loom::model(|| {
+        // 根节点已满, 插入会分裂根节点
+        let tree = filled(4);
+        let writer = {
+            let tree = tree.clone();
+            thread::spawn(move || tree.insert(10, 10))
+        };
+        assert_eq!(tree.get(&3), Some(3));
+        assert_eq!(writer.join().unwrap(), None);
+        assert_eq!(tree.get(&10), Some(10));
+    });
+}
+
+#[test]
+fn concurrent_inserts_into_same_leaf()
+{
+    loom::model(|| {
+        let tree = filled(3);
+        let handles: Vec<_> = [10, 11].into_iter().map(|key| {
+            let tree = tree.clone();
+            thread::spawn(move || tree.insert(key, key))
+        }).collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), None);
+        }
+        assert_eq!(tree.len(), 5);
+        assert!((0..3).chain(10..12).all(|key| tree.get(&key) == Some(key)));
+    });
+}
+
+#[test]
+fn remove_merge_races_with_insert()
+{
+    loom::model(|| {
+        // 两层的树, 删除会合并子节点并让根节点变空
+        let tree = filled(5);
+        tree.remove(&0);
+        tree.remove(&4);
+        let remover = {
+            let tree = tree.clone();
+            thread::spawn(move || tree.remove(&1))
+        };
+        assert_eq!(tree.insert(7, 7), None);
+        assert_eq!(remover.join().unwrap(), Some((1, 1)));
+        assert!([2, 3, 7].iter().all(|key| tree.contains_key(key)));
+        assert!(!tree.contains_key(&1));
+    });
+}
@@ -0,0 +1,37 @@
+use std::path::Path;
+use std::process::Command;
+
+const BARE_METAL: &str = "thumbv7em-none-eabihf";
+
+/// 关闭 std feature 编译库本身, target 为 None 时为主机编译
+fn check_without_std(target: Option<&str>)
+{
+    let mut cargo = Command::new(env!("CARGO"));
+    cargo.args(["check", "--lib", "--no-default-features", "--features", "serde", "--manifest-path", env!("CARGO_MANIFEST_PATH")])
+         .env("CARGO_TARGET_DIR", Path::new(env!("CARGO_TARGET_TMPDIR")).join("no_std"));
+    if let Some(target) = target { cargo.args(["--target", target]); }
+    let output = cargo.output().unwrap();
+    assert!(output.status.success(), "{target:?}\n{}", String::from_utf8_lossy(&output.stderr));
+}
+
+/// 库总是 #![no_std] 的, 所以在主机上编译也能发现对 std 的依赖
+#[test]
+fn builds_without_std()
+{
+    check_without_std(None);
+}
+
+/// 为裸机目标编译一次, 确认 alloc 以外的依赖也不需要 std
+#[test]
+#[ignore = "需要先执行 rustup target add thumbv7em-none-eabihf, 然后用 cargo test -- --ignored 运行, CI 的 no_std 任务会运行它"]
+fn builds_for_bare_metal()
+{
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    let sysroot = Command::new(rustc).args(["--print", "sysroot"]).output().unwrap().stdout;
+    let sysroot = String::from_utf8(sysroot).unwrap();
+    assert!(
+        Path::new(sysroot.trim()).join("lib/rustlib").join(BARE_METAL).exists(),
+        "没有安装 {BARE_METAL} 目标, 先执行 rustup target add {BARE_METAL}"
+    );
+    check_without_std(Some(BARE_METAL));
+}
@@ -0,0 +1,240 @@
+use naive_btree::*;
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::rc::Rc;
+
+/// 记录存活的实例数量, 可以指定在析构或者 clone 时 panic
+#[derive(Debug)]
+struct Tracked
+{
+    key: u32,
+    live: Rc<Cell<usize>>,
+    panic_on_drop: bool,
+    panic_on_clone: bool
+}
+
+impl Tracked
+{
+    fn new(key: u32, live: &Rc<Cell<usize>>) -> Self
+    {
+        live.set(live.get() + 1);
+        Self{ key, live: live.clone(), panic_on_drop: false, panic_on_clone: false }
+    }
+}
+
+impl Clone for Tracked
+{
+    fn clone(&self) -> Self {
+        if self.panic_on_clone { panic!("clone panic") }
+        Self::new(self.key, &self.live)
+    }
+}
+
+impl Drop for Tracked
+{
+    fn drop(&mut self) {
+        self.live.set(self.live.get() - 1);
+        if self.panic_on_drop { panic!("drop panic") }
+    }
+}
+
+impl PartialEq for Tracked
+{
+    fn eq(&self, other: &Self) -> bool { self.key == other.key }
+}
+
+impl Eq for Tracked {}
+
+impl PartialOrd for Tracked
+{
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+impl Ord for Tracked
+{
+    fn cmp(&self, other: &Self) -> Ordering { self.key.cmp(&other.key) }
+}
+
+#[test]
+fn drop_panic_still_drops_everything()
+{
+    let live = Rc::new(Cell::new(0));
+    let mut btree = Btree::new();
+    for i in 0..500 {
+        let mut value = Tracked::new(i, &live);
+        value.panic_on_drop = i == 123;
+        btree.insert(i, value);
+    }
+    let snapshot = btree.snapshot();
+    drop(btree);
+    assert_eq!(live.get(), 500);
+
+    assert!(catch_unwind(AssertUnwindSafe(|| drop(snapshot))).is_err());
+    assert_eq!(live.get(), 0);
+}
+
+#[test]
+fn panicking_ord_leaves_tree_intact()
+{
+    thread_local!(static PANIC_ON: Cell<Option<u32>> = const { Cell::new(None) });
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct Key(u32);
+    impl PartialOrd for Key
+    {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+    }
+    impl Ord for Key
+    {
+        fn cmp(&self, other: &Self) -> Ordering {
+            if PANIC_ON.get().is_some_and(|k| k == self.0 || k == other.0) { panic!("cmp panic") }
+            self.0.cmp(&other.0)
+        }
+    }
+
+    let mut btree = Btree::new();
+    for i in 0..300 {
+        btree.insert(Key(i * 2), i);
+    }
+    let _snapshot = btree.snapshot();
+    PANIC_ON.set(Some(301));
+    assert!(catch_unwind(AssertUnwindSafe(|| btree.insert(Key(301), 0))).is_err());
+    assert!(catch_unwind(AssertUnwindSafe(|| btree.remove(&Key(301)))).is_err());
+    PANIC_ON.set(None);
+
+    assert!(btree.iter().map(|(k, v)| (k.0, *v)).eq((0..300).map(|i| (i * 2, i))));
+    assert_eq!(btree.remove(&Key(300)), Some((Key(300), 150)));
+    btree.insert(Key(301), 0);
+    assert_eq!(btree.iter().count(), 300);
+}
+
+#[test]
+fn clone_panic_during_copy_on_write()
+{
+    let live = Rc::new(Cell::new(0));
+    let mut btree = Btree::new();
+    for i in 0..100 {
+        let mut key = Tracked::new(i, &live);
+        key.panic_on_clone = i == 50;
+        btree.insert(key, i);
+    }
+    let snapshot = btree.snapshot();
+    // 修改 50 需要复制包含它的节点, 复制到一半 panic. 传入的键和复制到一半的成员都被析构, 树里的成员不受影响
+    let result = catch_unwind(AssertUnwindSafe(|| btree.insert(Tracked::new(50, &live), 0)));
+    assert!(result.is_err());
+    assert!(btree.iter().map(|(k, v)| (k.key, *v)).eq((0..100).map(|i| (i, i))));
+    assert!(snapshot.iter().map(|(k, v)| (k.key, *v)).eq((0..100).map(|i| (i, i))));
+
+    drop(snapshot);
+    drop(btree);
+    assert_eq!(live.get(), 0);
+}
+
+#[test]
+fn clone_panic_while_updating_interval_map()
+{
+    thread_local!(static CLONES_LEFT: Cell<usize> = const { Cell::new(usize::MAX) });
+    thread_local!(static LIVE: Cell<usize> = const { Cell::new(0) });
+
+    /// 第 CLONES_LEFT 次 clone 时 panic
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+    struct Point(u32);
+    impl Point
+    {
+        fn new(x: u32) -> Self { LIVE.set(LIVE.get() + 1); Point(x) }
+    }
+    impl Clone for Point
+    {
+        fn clone(&self) -> Self {
+            match CLONES_LEFT.get() {
+                0 => panic!("clone panic"),
+                n => CLONES_LEFT.set(n - 1)
+            }
+            Point::new(self.0)
+        }
+    }
+    impl Drop for Point
+    {
+        fn drop(&mut self) { LIVE.set(LIVE.get() - 1) }
+    }
+
+    // 更新最大终点时 clone panic, 分裂和合并已经完成, 所有区间都还在树里
+    for budget in 0..200 {
+        let mut map = IntervalMap::new();
+        (0..200).for_each(|x| { map.insert(Point::new(x * 2)..=Point::new(x * 2 + 10), x); });
+        CLONES_LEFT.set(budget);
+        let inserted = (0..200).take_while(|x| catch_unwind(AssertUnwindSafe(|| map.insert(Point::new(x * 2 + 1)..=Point::new(x * 2 + 1), 0))).is_ok()).count() as u32;
+        CLONES_LEFT.set(usize::MAX);
+        let starts: Vec<_> = map.iter().map(|(start, _, _)| start.0).collect();
+        let mut expected: Vec<_> = (0..200).map(|x| x * 2).chain((0..inserted).map(|x| x * 2 + 1)).collect();
+        if starts.len() > expected.len() { expected.push(inserted * 2 + 1); }
+        expected.sort();
+        assert_eq!(starts, expected, "insert, budget {budget}");
+
+        CLONES_LEFT.set(budget);
+        let removed = (0..200).take_while(|x| catch_unwind(AssertUnwindSafe(|| map.remove(&Point::new(x * 2)))).is_ok()).count() as u32;
+        CLONES_LEFT.set(usize::MAX);
+        let starts: Vec<_> = map.iter().map(|(start, _, _)| start.0).filter(|start| start % 2 == 0).collect();
+        let first = if starts.len() < (200 - removed) as usize { removed + 1 } else { removed };
+        assert_eq!(starts, (first..200).map(|x| x * 2).collect::<Vec<_>>(), "remove, budget {budget}");
+
+        drop(map);
+        assert_eq!(LIVE.get(), 0, "budget {budget}");
+    }
+}
+
+#[test]
+fn transaction_rollback_panics_while_unwinding()
+{
+    thread_local!(static PANIC_ON: Cell<Option<u32>> = const { Cell::new(None) });
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct Key(u32);
+    impl PartialOrd for Key
+    {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+    }
+    impl Ord for Key
+    {
+        fn cmp(&self, other: &Self) -> Ordering {
+            if PANIC_ON.get().is_some_and(|k| k == self.0 || k == other.0) { panic!("cmp panic") }
+            self.0.cmp(&other.0)
+        }
+    }
+
+    let mut btree = Btree::new();
+    (0..100).for_each(|i| { btree.insert(Key(i), i); });
+    // 事务因为 panic 而回滚, 撤销插入 Key(200) 时比较再次 panic: 回滚停止, 不会中止进程
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        let mut tx = btree.transaction();
+        tx.remove(&Key(10));
+        tx.insert(Key(200), 200);
+        tx.remove(&Key(20));
+        PANIC_ON.set(Some(200));
+        panic!("validation failed");
+    }));
+    PANIC_ON.set(None);
+    assert!(result.is_err());
+    // Key(20) 已经恢复, 之后的撤销没有执行
+    assert_eq!(btree.get(&Key(20)), Some(&20));
+    assert_eq!(btree.get(&Key(200)), Some(&200));
+    assert_eq!(btree.get(&Key(10)), None);
+}
+
+#[test]
+fn iterators_dropped_midway()
+{
+    let live = Rc::new(Cell::new(0));
+    let mut btree = Btree::new();
+    for i in 0..200 {
+        btree.insert(i, Tracked::new(i, &live));
+    }
+    assert_eq!(btree.iter().take(10).count(), 10);
+    btree.iter_mut().take(20).for_each(|(_, v)| v.key += 1000);
+    assert_eq!(btree.range(50..).nth(3).map(|(k, _)| *k), Some(53));
+    assert_eq!(live.get(), 200);
+    assert_eq!(btree.get(&5).map(|v| v.key), Some(1005));
+    drop(btree);
+    assert_eq!(live.get(), 0);
+}
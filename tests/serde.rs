@@ -0,0 +1,40 @@
+//! 需要打开 serde feature: cargo test --features serde --test serde
+#![cfg(feature = "serde")]
+
+use naive_btree::Btree;
+
+#[derive(serde::Deserialize)]
+struct Strict
+{
+    #[serde(deserialize_with = "naive_btree::serde_support::deserialize_strict")]
+    tree: Btree<u32, String>
+}
+
+#[test]
+fn serde_round_trip()
+{
+    let mut btree = Btree::new();
+    for key in (0..200u32).rev() {
+        btree.insert(key, key.to_string());
+    }
+    let json = serde_json::to_string(&btree).unwrap();
+    assert!(json.starts_with(r#"{"0":"0","1":"1","#));
+
+    let loaded: Btree<u32, String> = serde_json::from_str(&json).unwrap();
+    assert!(loaded.iter().eq(btree.iter()));
+    assert_eq!(loaded.stats().entries, 200);
+}
+
+#[test]
+fn serde_unordered_input()
+{
+    let loaded: Btree<u32, u32> = serde_json::from_str(r#"{"1":1,"5":5,"3":3,"5":6,"0":0}"#).unwrap();
+    assert!(loaded.iter().map(|(k,v)| (*k,*v)).eq([(0,0), (1,1), (3,3), (5,6)]));
+
+    let strict: Strict = serde_json::from_str(r#"{"tree":{"1":"a","2":"b"}}"#).unwrap();
+    assert_eq!(strict.tree[2], "b");
+    let duplicate = serde_json::from_str::<Strict>(r#"{"tree":{"1":"a","1":"b"}}"#);
+    assert!(duplicate.map(|_| ()).unwrap_err().to_string().contains("键重复"));
+    let unordered = serde_json::from_str::<Strict>(r#"{"tree":{"2":"a","1":"b"}}"#);
+    assert!(unordered.map(|_| ()).unwrap_err().to_string().contains("键不是递增的"));
+}